@@ -1,7 +1,20 @@
-
 .globl sys_handler
+.globl syscall_kernel_rsp
+
+.section .data
+.align 8
+syscall_kernel_rsp:
+    .quad 0
+syscall_user_rsp:
+    .quad 0
+
+.section .text
 
 sys_handler:
+    mov qword ptr [rip + syscall_user_rsp], rsp
+    mov rsp, qword ptr [rip + syscall_kernel_rsp]
+    push qword ptr [rip + syscall_user_rsp]
+
     push r11
     push r10
     push r9
@@ -25,4 +38,39 @@ sys_handler:
     pop r10
     pop r11
 
+    pop rsp
     sysretq
+
+// int 0x80, the cpu already switched to the TSS stack and saved rip, cs,
+// rflags, rsp and ss
+//
+// same numbers and registers as the syscall instruction: number in rax,
+// arguments in rdi, rsi, rdx, r10, r8, r9. the i386 convention (ebx, ecx,
+// edx, esi, edi, ebp and the i386 numbers) is not decoded
+.globl int80_handler
+
+int80_handler:
+    push r11
+    push r10
+    push r9
+    push r8
+    push rdi
+    push rsi
+    push rdx
+    push rcx
+    push rax
+
+    mov rdi, rsp
+    call sys_dispatch
+
+    pop rax
+    pop rcx
+    pop rdx
+    pop rsi
+    pop rdi
+    pop r8
+    pop r9
+    pop r10
+    pop r11
+
+    iretq
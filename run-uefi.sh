@@ -1,4 +1,5 @@
 set -xe
+./user/install.sh
 cargo build --target x86_64-unknown-none
 cargo run --bin illuminos-uefi --features illuminos-boot-features
 
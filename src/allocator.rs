@@ -1,5 +1,7 @@
 pub mod paging;
 pub mod memory;
+pub mod slab;
+pub mod stats;
 
 use core::ops::{Deref, DerefMut};
 
@@ -1,32 +1,240 @@
-use core::{alloc::{GlobalAlloc, Layout}, sync::atomic::AtomicU64};
+use core::{alloc::{GlobalAlloc, Layout}, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
 
 
 
 use core::mem;
-use core::ptr;
-use linked_list_allocator::LockedHeap;
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
 use spin::Mutex;
-use x86_64::{structures::paging::{FrameAllocator, OffsetPageTable, Page, PageTableFlags, Size4KiB, Translate}, PhysAddr, VirtAddr};
+use x86_64::{registers::control::Cr3, structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate}, PhysAddr, VirtAddr};
 
 use crate::{println};
+use crate::thread::oom;
 
-use super::paging::{map_page, PagingManager};
+use super::paging::{map_page, physical_memory_offset, KernelFrameAllocator, PagingManager};
+use super::slab::{SlabAllocator, SlabStats, SIZE_CLASSES, SLAB_SIZE};
 
 
 
 pub const HEAP_START: VirtAddr = VirtAddr::new(0xFFFF800000000000);
-pub const HEAP_SIZE: u64 = 1024 * 1024 * 10; // 100 Mo
+pub const HEAP_SIZE: u64 = 1024 * 1024 * 10; // 10 Mo mapped at boot
+// the heap never grows past this, it stays in its level 4 entry
+pub const HEAP_MAX_SIZE: u64 = 1024 * 1024 * 512;
+// smallest growth, one 2 MiB page
+const HEAP_GROW_STEP: u64 = 1024 * 1024 * 2;
 
 pub fn init_heap(paging_manager: &mut PagingManager, flags: PageTableFlags) {
-    reserve_memory(HEAP_START, HEAP_SIZE, &mut paging_manager.mapper, &mut paging_manager.frame_allocator, flags);
+    if map_heap_range(&mut paging_manager.mapper, HEAP_START, HEAP_SIZE, flags) < HEAP_SIZE {
+        panic!("not enough memory for the kernel heap");
+    }
+
+    let mut kernel_heap = ALLOCATOR.heap.0.lock();
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START.as_u64() as usize, HEAP_SIZE as usize);
+        kernel_heap.heap.init(HEAP_START.as_u64() as usize, HEAP_SIZE as usize);
+    }
+    kernel_heap.page_table = Some(Cr3::read().0);
+    kernel_heap.flags = flags;
+}
+
+// lower the ceiling of the heap, it can't go below what is already mapped
+pub fn set_heap_limit(limit: u64) {
+    let mut kernel_heap = ALLOCATOR.heap.0.lock();
+    kernel_heap.limit = limit.clamp(kernel_heap.heap.size() as u64, HEAP_MAX_SIZE);
+}
+
+// maps [start, start + size) to new frames, with 2 MiB pages where the
+// address is aligned on them. returns how many bytes were mapped
+fn map_heap_range(mapper: &mut OffsetPageTable, start: VirtAddr, size: u64, flags: PageTableFlags) -> u64 {
+    let mut frame_allocator = KernelFrameAllocator::shared();
+    let mut mapped = 0;
+
+    while mapped < size {
+        let addr = start + mapped;
+
+        if addr.is_aligned(Size2MiB::SIZE) && size - mapped >= Size2MiB::SIZE {
+            if let Some(frame) = frame_allocator.allocate_huge_frame() {
+                let page = Page::<Size2MiB>::containing_address(addr);
+                match unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) } {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        unsafe { frame_allocator.deallocate_huge_frame(frame) };
+                        return mapped;
+                    }
+                }
+                mapped += Size2MiB::SIZE;
+                continue;
+            }
+        }
+
+        let Some(frame) = frame_allocator.allocate_frame() else {
+            return mapped;
+        };
+        let page = Page::<Size4KiB>::containing_address(addr);
+        match unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                return mapped;
+            }
+        }
+        mapped += Size4KiB::SIZE;
+    }
+
+    mapped
+}
+
+struct KernelHeap {
+    heap: Heap,
+    // kernel level 4 table, the new pages are mapped there
+    page_table: Option<PhysFrame>,
+    flags: PageTableFlags,
+    limit: u64,
+}
+
+impl KernelHeap {
+    // maps at least `min` more bytes at the top of the heap. frames come
+    // straight from the frame allocator, which never allocates on the heap
+    fn grow(&mut self, min: u64) -> Option<()> {
+        let page_table = self.page_table?;
+        let top = self.heap.top() as u64;
+        let end = HEAP_START.as_u64() + self.limit;
+        let by = min.max(HEAP_GROW_STEP).next_multiple_of(Size4KiB::SIZE).min(end.saturating_sub(top));
+        if by < min {
+            return None;
+        }
+
+        let phys_offset = physical_memory_offset();
+        let level_4_table = unsafe { &mut *(phys_offset + page_table.start_address().as_u64()).as_mut_ptr::<PageTable>() };
+        let mut mapper = unsafe { OffsetPageTable::new(level_4_table, phys_offset) };
+        let mapped = map_heap_range(&mut mapper, VirtAddr::new(top), by, self.flags);
+
+        // what was mapped is kept even when it isn't enough
+        unsafe { self.heap.extend(mapped as usize) };
+        (mapped >= min).then_some(())
     }
 }
 
+// linked list heap that maps more frames when it is full, up to its limit
+pub struct GrowableHeap(Mutex<KernelHeap>);
+
+impl GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut kernel_heap = self.0.lock();
+        if let Ok(ptr) = kernel_heap.heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // a new region at the top always fits the layout with its alignment
+        let min = (layout.size() + layout.align()) as u64;
+        if kernel_heap.grow(min).is_none() {
+            return ptr::null_mut();
+        }
+
+        kernel_heap.heap
+            .allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            unsafe { self.0.lock().heap.deallocate(ptr, layout) };
+        }
+    }
+}
+
+// small allocations go to the slab caches, the rest to the heap
+pub struct KernelAllocator {
+    slabs: SlabAllocator,
+    heap: GrowableHeap,
+}
+
+impl KernelAllocator {
+    unsafe fn try_alloc(&self, layout: Layout) -> *mut u8 {
+        match self.slabs.cache_for(layout) {
+            Some(cache) => cache.lock().alloc(|| unsafe {
+                self.heap.alloc(Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_SIZE))
+            }),
+            None => unsafe { self.heap.alloc(layout) },
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    // a failed allocation is tried again each time the out of memory killer
+    // gives memory back, it is given up once nothing is left to kill. when
+    // the largest process is the one running it is killed and this doesn't
+    // return
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = loop {
+            let ptr = unsafe { self.try_alloc(layout) };
+            if !ptr.is_null() || HEAP_OOM.swap(true, Ordering::SeqCst) {
+                break ptr;
+            }
+
+            let freed = oom::out_of_memory();
+            HEAP_OOM.store(false, Ordering::SeqCst);
+            if !freed {
+                break ptr;
+            }
+        };
+
+        #[cfg(feature = "heap-stats")]
+        super::stats::record_alloc(ptr, layout);
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-stats")]
+        super::stats::record_dealloc(ptr, layout);
+
+        match self.slabs.cache_for(layout) {
+            Some(cache) => unsafe { cache.lock().dealloc(ptr) },
+            None => unsafe { self.heap.dealloc(ptr, layout) },
+        }
+    }
+}
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    slabs: SlabAllocator::new(),
+    heap: GrowableHeap(Mutex::new(KernelHeap {
+        heap: Heap::empty(),
+        page_table: None,
+        flags: PageTableFlags::empty(),
+        limit: HEAP_MAX_SIZE,
+    })),
+};
+
+// (mapped, used, limit) bytes of the kernel heap, the lock may be held by the
+// failing allocation so this gives up instead of spinning
+pub fn heap_usage() -> Option<(u64, u64, u64)> {
+    let kernel_heap = ALLOCATOR.heap.0.try_lock()?;
+    Some((kernel_heap.heap.size() as u64, kernel_heap.heap.used() as u64, kernel_heap.limit))
+}
+
+pub fn slab_stats() -> [SlabStats; SIZE_CLASSES.len()] {
+    ALLOCATOR.slabs.stats()
+}
+
+// set while the out of memory killer runs for the heap, its own allocations
+// may fail too
+static HEAP_OOM: AtomicBool = AtomicBool::new(false);
+
+// the out of memory killer already ran for the allocation
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    match heap_usage() {
+        Some((size, used, limit)) => panic!(
+            "kernel heap exhausted: {} bytes aligned to {} requested, {} of {} bytes used, limit {} bytes",
+            layout.size(), layout.align(), used, size, limit
+        ),
+        None => panic!(
+            "kernel heap exhausted: {} bytes aligned to {} requested",
+            layout.size(), layout.align()
+        ),
+    }
+}
 
 
 // pub static mut ALLOCATOR: Option<AllocatorBase> = None;
@@ -191,18 +399,28 @@ static ALLOCATOR: LockedHeap = LockedHeap::empty();
 // pub static GLOBAL_ALLOCATOR: AllocImpl = AllocImpl;
 
 
+// None when there are no frames left, even after the out of memory killer
 pub fn reserve_memory(
     start: VirtAddr,
     size: u64,
     mapper: &mut OffsetPageTable,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     flags: PageTableFlags
-) {
+) -> Option<()> {
     let start_page = Page::containing_address(start);
     let end_page = Page::containing_address(start + size - 1u64);
 
     for page in Page::range(start_page, end_page + 1) {
-        let frame = frame_allocator.allocate_frame().expect("no frame available");
+        let frame = loop {
+            if let Some(frame) = frame_allocator.allocate_frame() {
+                break frame;
+            }
+            if !oom::out_of_memory() {
+                return None;
+            }
+        };
         map_page(page, frame, mapper, frame_allocator, flags);
     }
+
+    Some(())
 }
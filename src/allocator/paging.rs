@@ -1,9 +1,21 @@
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use bootloader_api::{info::{MemoryRegion, MemoryRegionKind, MemoryRegions}, BootInfo};
-use x86_64::{structures::{paging::{PageSize, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate}}, PhysAddr, VirtAddr};
+use x86_64::{structures::{paging::{PageSize, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate}}, PhysAddr, VirtAddr};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 use crate::math;
 use crate::info;
 
+static PHYS_MEM_OFFSET: AtomicU64 = AtomicU64::new(0);
+// physical address of the level 4 table the kernel booted with
+static KERNEL_PAGE_TABLE: AtomicU64 = AtomicU64::new(0);
+
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed))
+}
+
 pub fn get_physical_memory_offset(boot_info: &BootInfo) -> VirtAddr {
     let memory_regions = boot_info.memory_regions.iter();
     let usable_regions = memory_regions.filter(|r| r.kind == MemoryRegionKind::Usable);
@@ -27,6 +39,8 @@ pub fn get_physical_memory_offset(boot_info: &BootInfo) -> VirtAddr {
 pub unsafe fn init_paging(boot_info: &BootInfo) -> OffsetPageTable<'static> {
     let boot_info = boot_info;
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().expect("No physical memory offset found"));
+    PHYS_MEM_OFFSET.store(phys_mem_offset.as_u64(), Ordering::Relaxed);
+    KERNEL_PAGE_TABLE.store(x86_64::registers::control::Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
 
     let level_4_table = unsafe {
         active_level_4_table(phys_mem_offset)
@@ -37,6 +51,38 @@ pub unsafe fn init_paging(boot_info: &BootInfo) -> OffsetPageTable<'static> {
     }
 }
 
+// level 4 entries from here on map the kernel, below is user space
+pub const KERNEL_HALF_START: usize = 256;
+
+// every kernel level 4 entry gets a table at boot, so what the kernel maps
+// later lands in tables the processes already share
+fn populate_kernel_half(mapper: &mut OffsetPageTable, frame_allocator: &mut KernelFrameAllocator) {
+    let phys_offset = mapper.phys_offset();
+
+    for entry in mapper.level_4_table_mut().iter_mut().skip(KERNEL_HALF_START) {
+        if !entry.is_unused() {
+            continue;
+        }
+
+        let frame = frame_allocator.allocate_frame().expect("no frame for a kernel page table");
+        unsafe {
+            (phys_offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>().write(PageTable::new());
+        }
+        entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+}
+
+pub fn kernel_page_table_frame() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PAGE_TABLE.load(Ordering::Relaxed)))
+}
+
+// mapper on the kernel's level 4 table, whatever table is active
+pub fn kernel_page_table() -> OffsetPageTable<'static> {
+    let phys_offset = physical_memory_offset();
+    let virt = phys_offset + KERNEL_PAGE_TABLE.load(Ordering::Relaxed);
+    unsafe { OffsetPageTable::new(&mut *virt.as_mut_ptr::<PageTable>(), phys_offset) }
+}
+
 unsafe fn active_level_4_table(phys_mem_offset: VirtAddr) -> &'static mut PageTable {
     let frame = x86_64::registers::control::Cr3::read().0;
     let phys = frame.start_address();
@@ -56,32 +102,167 @@ pub fn map_page(
     map_result.expect("map_to failed").flush();
 }
 
+// every PagingManager hands out frames from this one, a frame can't be given
+// to two page tables. nothing under this lock may use the kernel heap, the
+// heap itself takes frames from here when it grows
+static FRAMES: Mutex<Option<FrameAllocatorState>> = Mutex::new(None);
+
+// owners of the frames mapped by more than one page table, a frame that isn't
+// here has a single owner
+static FRAME_REFS: Mutex<BTreeMap<PhysFrame, usize>> = Mutex::new(BTreeMap::new());
+
+// end of the free list
+const NO_FRAME: u64 = u64::MAX;
+
+struct FrameAllocatorState {
+    // boot info lives for the whole kernel
+    memory_map: &'static [MemoryRegion],
+    region: usize,
+    next: u64,
+    // freed frames are chained through their first word
+    free_list: u64,
+    free_listed: u64,
+}
+
+impl FrameAllocatorState {
+    fn next_free(frame: u64) -> *mut u64 {
+        (physical_memory_offset() + frame).as_mut_ptr::<u64>()
+    }
+
+    fn allocate(&mut self) -> Option<PhysFrame> {
+        if self.free_list != NO_FRAME {
+            let frame = self.free_list;
+            self.free_list = unsafe { Self::next_free(frame).read() };
+            self.free_listed -= 1;
+            return Some(PhysFrame::containing_address(PhysAddr::new(frame)));
+        }
+
+        while let Some(region) = self.memory_map.get(self.region) {
+            if region.kind == MemoryRegionKind::Usable {
+                let start = self.next.max(region.start.next_multiple_of(Size4KiB::SIZE));
+                if start + Size4KiB::SIZE <= region.end {
+                    self.next = start + Size4KiB::SIZE;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(start)));
+                }
+            }
+            self.region += 1;
+        }
+
+        None
+    }
+
+    // `count` frames in a row starting on `align`, taken from memory never
+    // handed out since the free list isn't ordered. the frames skipped to
+    // get there go to the free list
+    fn allocate_contiguous(&mut self, count: u64, align: u64) -> Option<PhysAddr> {
+        let len = count * Size4KiB::SIZE;
+        let mut region_index = self.region;
+
+        let start = loop {
+            let region = self.memory_map.get(region_index)?;
+            if region.kind == MemoryRegionKind::Usable {
+                let start = self.next.max(region.start).next_multiple_of(align);
+                if start + len <= region.end {
+                    break start;
+                }
+            }
+            region_index += 1;
+        };
+
+        for index in self.region..=region_index {
+            let region = self.memory_map[index];
+            if region.kind != MemoryRegionKind::Usable {
+                continue;
+            }
+            let from = self.next.max(region.start.next_multiple_of(Size4KiB::SIZE));
+            let to = if index == region_index { start } else { region.end };
+            for frame in (from..to).step_by(Size4KiB::SIZE as usize) {
+                if frame + Size4KiB::SIZE <= to {
+                    self.deallocate(PhysFrame::containing_address(PhysAddr::new(frame)));
+                }
+            }
+        }
+
+        self.region = region_index;
+        self.next = start + len;
+        Some(PhysAddr::new(start))
+    }
+
+    fn deallocate(&mut self, frame: PhysFrame) {
+        let frame = frame.start_address().as_u64();
+        unsafe { Self::next_free(frame).write(self.free_list) };
+        self.free_list = frame;
+        self.free_listed += 1;
+    }
+
+    // frames on the free list and in the part of the memory map not handed
+    // out yet
+    fn free_frames(&self) -> u64 {
+        let untouched: u64 = self.memory_map[self.region..]
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+            .map(|region| {
+                let start = self.next.max(region.start.next_multiple_of(Size4KiB::SIZE));
+                region.end.saturating_sub(start) / Size4KiB::SIZE
+            })
+            .sum();
+
+        self.free_listed + untouched
+    }
+}
+
+pub fn free_frames() -> u64 {
+    FRAMES.lock().as_ref().map_or(0, |frames| frames.free_frames())
+}
+
 #[derive(Clone, Debug)]
 pub struct KernelFrameAllocator<'a> {
-    memory_map: &'a [MemoryRegion],
-    freeed_frame: Vec<PhysFrame>,
-    use_free_frame: bool,
-    next: usize,
+    memory_map: PhantomData<&'a [MemoryRegion]>,
 }
 
 impl<'a> KernelFrameAllocator<'a> {
     pub unsafe fn init(boot_info: &'a BootInfo) -> Self {
-        KernelFrameAllocator {
-            memory_map: &boot_info.memory_regions,
-            freeed_frame: Vec::new(),
-            use_free_frame: false,
+        let memory_map: &'static [MemoryRegion] = unsafe {
+            core::slice::from_raw_parts(boot_info.memory_regions.as_ptr(), boot_info.memory_regions.len())
+        };
+
+        FRAMES.lock().get_or_insert_with(|| FrameAllocatorState {
+            memory_map,
+            region: 0,
             next: 0,
-        }
+            free_list: NO_FRAME,
+            free_listed: 0,
+        });
+
+        KernelFrameAllocator { memory_map: PhantomData }
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.kind == MemoryRegionKind::Usable);
-        let addr_ranges = usable_regions.map(|r| r.start..r.end);
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    // handle on the allocator for code that has no PagingManager, it must
+    // have been initialised by PagingManager::new
+    pub fn shared() -> KernelFrameAllocator<'static> {
+        KernelFrameAllocator { memory_map: PhantomData }
     }
 
+    // one more owner for `frame`, it is only freed when every owner has
+    // deallocated it
+    pub fn add_ref(&mut self, frame: PhysFrame) {
+        *FRAME_REFS.lock().entry(frame).or_insert(1) += 1;
+    }
+
+    // 512 contiguous frames for a 2 MiB page
+    pub fn allocate_huge_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let count = Size2MiB::SIZE / Size4KiB::SIZE;
+        let start = FRAMES.lock().as_mut()?.allocate_contiguous(count, Size2MiB::SIZE)?;
+        PhysFrame::from_start_address(start).ok()
+    }
+
+    // the frames of a 2 MiB page come back one by one, they are never merged
+    pub unsafe fn deallocate_huge_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let first = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+        for frame in PhysFrame::range(first, first + Size2MiB::SIZE / Size4KiB::SIZE) {
+            unsafe { self.deallocate_frame(frame) };
+        }
+    }
 
     pub fn allocate_frames(&mut self, size: usize) -> Option<Vec<PhysFrame>> {
         let size_frame = Size4KiB::SIZE;
@@ -105,8 +286,9 @@ pub struct PagingManager<'p> {
 impl<'p> PagingManager<'p> {
     pub unsafe fn new(boot_info: &'p BootInfo) -> Self {
         let mut boot_info = boot_info;
-        let mapper = unsafe { init_paging(boot_info) };
-        let frame_allocator = unsafe { KernelFrameAllocator::init(&mut boot_info) };
+        let mut mapper = unsafe { init_paging(boot_info) };
+        let mut frame_allocator = unsafe { KernelFrameAllocator::init(&mut boot_info) };
+        populate_kernel_half(&mut mapper, &mut frame_allocator);
         PagingManager {
             mapper,
             frame_allocator,
@@ -130,6 +312,18 @@ impl<'p> PagingManager<'p> {
         map_page(page, frame, &mut self.mapper, &mut self.frame_allocator, flags);
     }
 
+    pub fn map_huge_page(
+        &mut self,
+        page: Page<Size2MiB>,
+        frame: PhysFrame<Size2MiB>,
+        flags: PageTableFlags,
+    ) {
+        let map_result = unsafe { self.mapper.map_to(page, frame, flags, &mut self.frame_allocator) };
+        map_result.expect("map_to failed").flush();
+    }
+
+    // maps [virt_addr, virt_addr + size) to the physical memory at phys_addr,
+    // with 2 MiB pages wherever both sides are aligned on them
     pub fn map_memory(
         &mut self,
         virt_addr: VirtAddr,
@@ -137,40 +331,44 @@ impl<'p> PagingManager<'p> {
         phys_addr: PhysAddr,
         flags: PageTableFlags,
     ) {
-        let page = Page::containing_address(virt_addr);
-        let frame = PhysFrame::containing_address(phys_addr);
-        let page_count = ((size + 4095) / 4096) as u64;
-        let pages = (0..page_count).map(|i| page + i);
-        for page in pages {
-            self.map_page(page, frame, flags);
-        }
-
-    }
+        let size = (size as u64).next_multiple_of(Size4KiB::SIZE);
+        let mut offset = 0;
 
+        while offset < size {
+            let virt = virt_addr + offset;
+            let phys = phys_addr + offset;
 
+            if virt.is_aligned(Size2MiB::SIZE) && phys.is_aligned(Size2MiB::SIZE) && size - offset >= Size2MiB::SIZE {
+                self.map_huge_page(Page::containing_address(virt), PhysFrame::containing_address(phys), flags);
+                offset += Size2MiB::SIZE;
+            } else {
+                self.map_page(Page::containing_address(virt), PhysFrame::containing_address(phys), flags);
+                offset += Size4KiB::SIZE;
+            }
+        }
+    }
 }
 
 unsafe impl<'a> FrameAllocator<Size4KiB> for KernelFrameAllocator<'a> {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        if self.use_free_frame {
-            let frame = self.freeed_frame.get(self.next);
-            self.next += 1;
-            return frame.copied();
-        } else {
-            let frame = self.usable_frames().nth(self.next);
-
-            if frame.is_none() {
-                self.use_free_frame = true;
-                return self.allocate_frame();
-            }
-            self.next += 1;
-            frame
-        }
+        FRAMES.lock().as_mut()?.allocate()
     }
 }
 
 impl<'a> FrameDeallocator<Size4KiB> for KernelFrameAllocator<'a>{
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
-        self.freeed_frame.push(frame);
+        let mut refs = FRAME_REFS.lock();
+        if let Some(owners) = refs.get_mut(&frame) {
+            *owners -= 1;
+            if *owners == 1 {
+                refs.remove(&frame);
+            }
+            return;
+        }
+        drop(refs);
+
+        if let Some(frames) = FRAMES.lock().as_mut() {
+            frames.deallocate(frame);
+        }
     }
 }
@@ -0,0 +1,125 @@
+// caches of fixed size objects in front of the kernel heap
+//
+// small allocations (inodes, directory entries, strings...) are served from
+// 4 KiB slabs cut in objects of one size, so they don't fragment the linked
+// list heap. slabs are taken from the heap and never given back
+
+use core::alloc::Layout;
+use core::ptr;
+use spin::Mutex;
+
+pub const SIZE_CLASSES: [usize; 7] = [16, 32, 64, 128, 256, 512, 1024];
+pub const SLAB_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlabStats {
+    pub object_size: usize,
+    pub slabs: usize,
+    // objects handed out and not freed yet
+    pub in_use: usize,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+pub struct SlabCache {
+    object_size: usize,
+    // free objects are chained through their first word
+    free_list: *mut u8,
+    slabs: usize,
+    in_use: usize,
+    allocations: u64,
+    frees: u64,
+}
+
+// the objects are only reached through the cache lock
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    pub const fn new(object_size: usize) -> Self {
+        SlabCache {
+            object_size,
+            free_list: ptr::null_mut(),
+            slabs: 0,
+            in_use: 0,
+            allocations: 0,
+            frees: 0,
+        }
+    }
+
+    // `new_slab` gives SLAB_SIZE bytes aligned to SLAB_SIZE, or null
+    pub fn alloc(&mut self, new_slab: impl FnOnce() -> *mut u8) -> *mut u8 {
+        if self.free_list.is_null() {
+            let slab = new_slab();
+            if slab.is_null() {
+                return ptr::null_mut();
+            }
+
+            for offset in (0..SLAB_SIZE).step_by(self.object_size).rev() {
+                unsafe { self.push(slab.add(offset)) };
+            }
+            self.slabs += 1;
+        }
+
+        let object = self.free_list;
+        self.free_list = unsafe { (object as *mut *mut u8).read() };
+        self.in_use += 1;
+        self.allocations += 1;
+        object
+    }
+
+    pub unsafe fn dealloc(&mut self, object: *mut u8) {
+        unsafe { self.push(object) };
+        self.in_use -= 1;
+        self.frees += 1;
+    }
+
+    unsafe fn push(&mut self, object: *mut u8) {
+        unsafe { (object as *mut *mut u8).write(self.free_list) };
+        self.free_list = object;
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            object_size: self.object_size,
+            slabs: self.slabs,
+            in_use: self.in_use,
+            allocations: self.allocations,
+            frees: self.frees,
+        }
+    }
+}
+
+pub struct SlabAllocator {
+    caches: [Mutex<SlabCache>; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        SlabAllocator {
+            caches: [
+                Mutex::new(SlabCache::new(SIZE_CLASSES[0])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[1])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[2])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[3])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[4])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[5])),
+                Mutex::new(SlabCache::new(SIZE_CLASSES[6])),
+            ],
+        }
+    }
+
+    // objects are aligned on their size, so the alignment counts as size
+    pub fn cache_for(&self, layout: Layout) -> Option<&Mutex<SlabCache>> {
+        let size = layout.size().max(layout.align());
+        let index = SIZE_CLASSES.iter().position(|&class| size <= class)?;
+        Some(&self.caches[index])
+    }
+
+    pub fn stats(&self) -> [SlabStats; SIZE_CLASSES.len()] {
+        let mut stats = [SlabStats::default(); SIZE_CLASSES.len()];
+        for (stat, cache) in stats.iter_mut().zip(&self.caches) {
+            *stat = cache.lock().stats();
+        }
+        stats
+    }
+}
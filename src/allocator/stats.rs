@@ -0,0 +1,199 @@
+// kernel heap statistics, shown by the `heap` console command and /proc/heap
+//
+// the accounting is only compiled with the `heap-stats` feature, debug builds
+// with it also keep a table of the live allocations and who made them
+
+use alloc::string::String;
+use core::alloc::Layout;
+use core::fmt::Write;
+
+use super::memory::{heap_usage, slab_stats};
+use super::slab::SIZE_CLASSES;
+
+#[cfg(feature = "heap-stats")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// the slab classes, then everything bigger
+#[cfg(feature = "heap-stats")]
+const CLASSES: usize = SIZE_CLASSES.len() + 1;
+
+#[cfg(feature = "heap-stats")]
+struct Accounting {
+    in_use: AtomicU64,
+    peak: AtomicU64,
+    allocations: [AtomicU64; CLASSES],
+    frees: [AtomicU64; CLASSES],
+}
+
+#[cfg(feature = "heap-stats")]
+static ACCOUNTING: Accounting = Accounting {
+    in_use: AtomicU64::new(0),
+    peak: AtomicU64::new(0),
+    allocations: [const { AtomicU64::new(0) }; CLASSES],
+    frees: [const { AtomicU64::new(0) }; CLASSES],
+};
+
+#[cfg(feature = "heap-stats")]
+fn class_of(layout: Layout) -> usize {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| size <= class).unwrap_or(SIZE_CLASSES.len())
+}
+
+// called by the global allocator, nothing here may allocate
+#[cfg(feature = "heap-stats")]
+pub fn record_alloc(ptr: *mut u8, layout: Layout) {
+    if ptr.is_null() {
+        return;
+    }
+
+    let in_use = ACCOUNTING.in_use.fetch_add(layout.size() as u64, Ordering::Relaxed) + layout.size() as u64;
+    ACCOUNTING.peak.fetch_max(in_use, Ordering::Relaxed);
+    ACCOUNTING.allocations[class_of(layout)].fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(debug_assertions)]
+    live::insert(ptr as u64, layout.size() as u64, live::caller());
+}
+
+#[cfg(feature = "heap-stats")]
+pub fn record_dealloc(ptr: *mut u8, layout: Layout) {
+    ACCOUNTING.in_use.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+    ACCOUNTING.frees[class_of(layout)].fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(debug_assertions)]
+    live::remove(ptr as u64);
+}
+
+#[cfg(all(feature = "heap-stats", debug_assertions))]
+mod live {
+    use alloc::vec::Vec;
+    use core::arch::asm;
+    use spin::Mutex;
+
+    // fixed size, the table can't allocate from the heap it watches
+    const CAPACITY: usize = 4096;
+    // frames between the allocator and the code asking for memory:
+    // record_alloc, the global allocator and the alloc shim
+    const CALLER_DEPTH: usize = 3;
+    const KERNEL_HALF: u64 = 0xFFFF_8000_0000_0000;
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct LiveAllocation {
+        pub ptr: u64,
+        pub size: u64,
+        pub caller: u64,
+    }
+
+    struct LiveTable {
+        entries: [LiveAllocation; CAPACITY],
+        // allocations not recorded because the table was full
+        dropped: u64,
+    }
+
+    static LIVE: Mutex<LiveTable> = Mutex::new(LiveTable {
+        entries: [LiveAllocation { ptr: 0, size: 0, caller: 0 }; CAPACITY],
+        dropped: 0,
+    });
+
+    // return address of the allocating code, found through the frame
+    // pointers, 0 when the chain looks broken as it does when the kernel is
+    // built without force-frame-pointers
+    #[inline(always)]
+    pub fn caller() -> u64 {
+        let mut rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+
+        for _ in 0..CALLER_DEPTH {
+            if rbp < KERNEL_HALF || !rbp.is_multiple_of(8) {
+                return 0;
+            }
+            rbp = unsafe { *(rbp as *const u64) };
+        }
+
+        if rbp < KERNEL_HALF || !rbp.is_multiple_of(8) {
+            return 0;
+        }
+        unsafe { *((rbp + 8) as *const u64) }
+    }
+
+    pub fn insert(ptr: u64, size: u64, caller: u64) {
+        let mut table = LIVE.lock();
+        match table.entries.iter_mut().find(|entry| entry.ptr == 0) {
+            Some(entry) => *entry = LiveAllocation { ptr, size, caller },
+            None => table.dropped += 1,
+        }
+    }
+
+    pub fn remove(ptr: u64) {
+        let mut table = LIVE.lock();
+        if let Some(entry) = table.entries.iter_mut().find(|entry| entry.ptr == ptr) {
+            entry.ptr = 0;
+        }
+    }
+
+    // copy of the table, the vector is sized before taking the lock so the
+    // copy doesn't allocate while holding it
+    pub fn snapshot() -> (Vec<LiveAllocation>, u64) {
+        let count = LIVE.lock().entries.iter().filter(|entry| entry.ptr != 0).count();
+        // room for what is allocated meanwhile, this one included
+        let mut live = Vec::with_capacity(count + 16);
+
+        let table = LIVE.lock();
+        for entry in table.entries.iter().filter(|entry| entry.ptr != 0) {
+            if live.len() == live.capacity() {
+                break;
+            }
+            live.push(*entry);
+        }
+
+        (live, table.dropped)
+    }
+}
+
+pub fn report() -> String {
+    let mut out = String::new();
+
+    match heap_usage() {
+        Some((size, used, limit)) => {
+            let _ = writeln!(out, "heap: {} used, {} mapped, limit {}", used, size, limit);
+        }
+        None => {
+            let _ = writeln!(out, "heap: busy");
+        }
+    }
+
+    let _ = writeln!(out, "slab      size  slabs  in use  allocs  frees");
+    for stats in slab_stats() {
+        let _ = writeln!(
+            out, "slab {:>9} {:>6} {:>7} {:>7} {:>6}",
+            stats.object_size, stats.slabs, stats.in_use, stats.allocations, stats.frees
+        );
+    }
+
+    #[cfg(feature = "heap-stats")]
+    {
+        let _ = writeln!(
+            out, "accounted: {} bytes in use, peak {}",
+            ACCOUNTING.in_use.load(Ordering::Relaxed), ACCOUNTING.peak.load(Ordering::Relaxed)
+        );
+        for class in 0..CLASSES {
+            let name = SIZE_CLASSES.get(class).map_or(String::from("large"), |size| alloc::format!("<={}", size));
+            let _ = writeln!(
+                out, "class {:>6}: {} allocs, {} frees",
+                name,
+                ACCOUNTING.allocations[class].load(Ordering::Relaxed),
+                ACCOUNTING.frees[class].load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    #[cfg(all(feature = "heap-stats", debug_assertions))]
+    {
+        let (live, dropped) = live::snapshot();
+        let _ = writeln!(out, "live allocations: {} ({} not tracked)", live.len(), dropped);
+        for entry in live {
+            let _ = writeln!(out, "  {:#x} {} bytes from {:#x}", entry.ptr, entry.size, entry.caller);
+        }
+    }
+
+    out
+}
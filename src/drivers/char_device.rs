@@ -0,0 +1,8 @@
+pub mod uart;
+
+// a byte stream device, the counterpart of Disk for devices without sectors
+pub trait CharDevice {
+    // bytes already received, 0 when there are none
+    fn read_bytes(&mut self, buf: &mut [u8]) -> usize;
+    fn write_bytes(&mut self, data: &[u8]);
+}
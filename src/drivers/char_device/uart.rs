@@ -0,0 +1,266 @@
+// 16550 UARTs on the legacy COM1 to COM4 ports
+//
+// received bytes come in through IRQ4 (COM1, COM3) and IRQ3 (COM2, COM4) and
+// wait in a ring buffer per port. a port in console mode hands them to the
+// kernel console instead, so what is typed on it reaches stdin. transmit is
+// polled
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use alloc::vec::Vec;
+use x86_64::instructions::interrupts;
+
+use super::CharDevice;
+use crate::io::console;
+use crate::io::irq_mutex::IrqMutex;
+use crate::io::port::{Device, DeviceError, Readiness};
+use crate::io::ring_buffer::RingBuffer;
+use crate::io::{inb, outb};
+use crate::thread;
+
+const COM_BASES: [u16; 4] = [0x3F8, 0x2F8, 0x3E8, 0x2E8];
+
+pub const DEFAULT_BAUD: u32 = 115200;
+// the divisor latch divides this
+const UART_CLOCK: u32 = 115200;
+
+// received bytes kept until read, more are dropped
+const INPUT_SIZE: usize = 1024;
+
+// register offsets
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+// with DLAB set, DATA and INTERRUPT_ENABLE hold the divisor
+const LCR_DLAB: u8 = 0x80;
+const LCR_8N1: u8 = 0x03;
+// enabled, both FIFOs cleared, interrupt at 14 bytes
+const FCR_ENABLE: u8 = 0xC7;
+// DTR, RTS, and OUT2 which routes the interrupt to the PIC
+const MCR_NORMAL: u8 = 0x0F;
+const MCR_LOOPBACK: u8 = 0x1E;
+const IER_RECEIVED: u8 = 0x01;
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+// ioctl requests, the argument and the result are the baud rate
+pub const UART_SET_BAUD: u64 = 1;
+pub const UART_GET_BAUD: u64 = 2;
+
+struct ComState {
+    present: AtomicBool,
+    baud: AtomicU32,
+    console: AtomicBool,
+    input: IrqMutex<RingBuffer<INPUT_SIZE>>,
+}
+
+impl ComState {
+    const fn new() -> Self {
+        ComState {
+            present: AtomicBool::new(false),
+            baud: AtomicU32::new(0),
+            console: AtomicBool::new(false),
+            input: IrqMutex::new(RingBuffer::new()),
+        }
+    }
+}
+
+static COMS: [ComState; 4] = [ComState::new(), ComState::new(), ComState::new(), ComState::new()];
+// set once init went through every port
+static PROBED: AtomicBool = AtomicBool::new(false);
+
+// a detected port, COM1 is index 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uart {
+    index: usize,
+}
+
+// finds and sets up the ports, the ones that answer are returned
+pub fn init(baud: u32) -> Vec<Uart> {
+    let mut uarts = Vec::new();
+    for (index, &base) in COM_BASES.iter().enumerate() {
+        if probe(base, baud) {
+            COMS[index].present.store(true, Ordering::Release);
+            COMS[index].baud.store(baud, Ordering::Relaxed);
+            uarts.push(Uart { index });
+        }
+    }
+    PROBED.store(true, Ordering::Release);
+    uarts
+}
+
+pub fn get(index: usize) -> Option<Uart> {
+    COMS.get(index)
+        .filter(|com| com.present.load(Ordering::Acquire))
+        .map(|_| Uart { index })
+}
+
+// the kernel log goes to COM1. before init it is written blindly, after
+// only when the port answered. the probe itself logs nothing, COM1 is in
+// loopback meanwhile
+pub fn write_log_byte(byte: u8) {
+    if !PROBED.load(Ordering::Acquire) {
+        Uart { index: 0 }.write_byte(byte);
+    } else if let Some(com1) = get(0) {
+        com1.write_byte(byte);
+    }
+}
+
+fn divisor(baud: u32) -> Option<u16> {
+    if baud == 0 || !UART_CLOCK.is_multiple_of(baud) {
+        return None;
+    }
+    u16::try_from(UART_CLOCK / baud).ok()
+}
+
+fn set_divisor(base: u16, divisor: u16) {
+    let lcr = inb(base + LINE_CONTROL);
+    outb(base + LINE_CONTROL, lcr | LCR_DLAB);
+    outb(base + DATA, divisor as u8);
+    outb(base + INTERRUPT_ENABLE, (divisor >> 8) as u8);
+    outb(base + LINE_CONTROL, lcr & !LCR_DLAB);
+}
+
+// a missing port reads back all ones, a present one keeps the scratch value
+// and echoes a byte in loopback
+fn probe(base: u16, baud: u32) -> bool {
+    let Some(divisor) = divisor(baud) else {
+        return false;
+    };
+
+    outb(base + SCRATCH, 0x5A);
+    if inb(base + SCRATCH) != 0x5A {
+        return false;
+    }
+
+    outb(base + INTERRUPT_ENABLE, 0);
+    outb(base + LINE_CONTROL, LCR_8N1);
+    set_divisor(base, divisor);
+    outb(base + FIFO_CONTROL, FCR_ENABLE);
+
+    outb(base + MODEM_CONTROL, MCR_LOOPBACK);
+    outb(base + DATA, 0xAE);
+    if inb(base + DATA) != 0xAE {
+        return false;
+    }
+
+    outb(base + MODEM_CONTROL, MCR_NORMAL);
+    outb(base + INTERRUPT_ENABLE, IER_RECEIVED);
+    true
+}
+
+// called from the IRQ3 and IRQ4 handlers, the two lines are each shared by
+// two ports so every port is drained
+pub fn handle_interrupt() {
+    for (index, com) in COMS.iter().enumerate() {
+        if !com.present.load(Ordering::Acquire) {
+            continue;
+        }
+
+        let base = COM_BASES[index];
+        while inb(base + LINE_STATUS) & LSR_DATA_READY != 0 {
+            let byte = inb(base + DATA);
+            if com.console.load(Ordering::Relaxed) {
+                // terminals send a carriage return for enter and don't echo
+                let byte = if byte == b'\r' { b'\n' } else { byte };
+                Uart { index }.write_byte(byte);
+                console::push_input(&[byte]);
+            } else {
+                com.input.lock().push(byte);
+            }
+        }
+    }
+}
+
+impl Uart {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn base(&self) -> u16 {
+        COM_BASES[self.index]
+    }
+
+    pub fn baud(&self) -> u32 {
+        COMS[self.index].baud.load(Ordering::Relaxed)
+    }
+
+    // only rates that divide the clock exactly
+    pub fn set_baud(&self, baud: u32) -> Result<(), DeviceError> {
+        let divisor = divisor(baud).ok_or(DeviceError::InvalidArgument)?;
+        // the interrupt handler would read the divisor instead of data
+        interrupts::without_interrupts(|| set_divisor(self.base(), divisor));
+        COMS[self.index].baud.store(baud, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // received bytes go to the kernel console instead of the port buffer
+    pub fn set_console(&self, console: bool) {
+        COMS[self.index].console.store(console, Ordering::Relaxed);
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        while inb(self.base() + LINE_STATUS) & LSR_THR_EMPTY == 0 {}
+        outb(self.base() + DATA, byte);
+    }
+}
+
+impl CharDevice for Uart {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> usize {
+        COMS[self.index].input.lock().read(buf)
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.write_byte(byte);
+        }
+    }
+}
+
+impl Device for Uart {
+    // what was received so far, waits for a byte when nothing was
+    fn read(&self, buf: &mut [u8], _offset: u64) -> Result<usize, DeviceError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let len = COMS[self.index].input.lock().read(buf);
+            if len > 0 {
+                return Ok(len);
+            }
+            thread::wait_for_interrupt();
+        }
+    }
+
+    fn write(&self, buf: &[u8], _offset: u64) -> Result<usize, DeviceError> {
+        for &byte in buf {
+            self.write_byte(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<u64, DeviceError> {
+        match request {
+            UART_SET_BAUD => {
+                let baud = u32::try_from(arg).map_err(|_| DeviceError::InvalidArgument)?;
+                self.set_baud(baud).map(|_| 0)
+            }
+            UART_GET_BAUD => Ok(self.baud() as u64),
+            _ => Err(DeviceError::NotSupported),
+        }
+    }
+
+    fn poll(&self) -> Readiness {
+        if !COMS[self.index].input.lock().is_empty() {
+            Readiness::READABLE | Readiness::WRITABLE
+        } else {
+            Readiness::WRITABLE
+        }
+    }
+}
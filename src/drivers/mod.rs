@@ -1,2 +1,4 @@
 pub mod keyboard;
-pub mod disk;
\ No newline at end of file
+pub mod char_device;
+pub mod disk;
+pub mod rtc;
@@ -0,0 +1,192 @@
+use crate::io::{inb, outb};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// Bit 7 of the address port disables NMI while we talk to the CMOS
+const CMOS_NMI_DISABLE: u8 = 0x80;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_CENTURY: u8 = 0x32;
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_B_24_HOUR: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOUR_PM: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub const UNIX_EPOCH: DateTime = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+
+    // seconds since 1970-01-01 00:00:00 UTC
+    pub fn to_unix_timestamp(self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let secs = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        (days * 86400 + secs).max(0) as u64
+    }
+
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let days = (timestamp / 86400) as i64;
+        let secs = timestamp % 86400;
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs / 3600) as u8,
+            minute: ((secs % 3600) / 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    // FAT date: bits 15-9 year since 1980, 8-5 month, 4-0 day
+    pub fn fat_date(&self) -> u16 {
+        let year = self.year.saturating_sub(1980).min(127);
+        (year << 9) | ((self.month as u16) << 5) | self.day as u16
+    }
+
+    // FAT time: bits 15-11 hours, 10-5 minutes, 4-0 seconds / 2
+    pub fn fat_time(&self) -> u16 {
+        ((self.hour as u16) << 11) | ((self.minute as u16) << 5) | (self.second as u16 / 2)
+    }
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn read_register(reg: u8) -> u8 {
+    outb(CMOS_ADDRESS, CMOS_NMI_DISABLE | reg);
+    let value = inb(CMOS_DATA);
+    // NMIs are enabled again, the bit stays as written
+    outb(CMOS_ADDRESS, reg);
+    value
+}
+
+fn is_update_in_progress() -> bool {
+    read_register(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn read_raw() -> RawTime {
+    while is_update_in_progress() {}
+
+    RawTime {
+        second: read_register(RTC_SECONDS),
+        minute: read_register(RTC_MINUTES),
+        hour: read_register(RTC_HOURS),
+        day: read_register(RTC_DAY),
+        month: read_register(RTC_MONTH),
+        year: read_register(RTC_YEAR),
+        century: read_register(RTC_CENTURY),
+    }
+}
+
+pub fn read_rtc() -> DateTime {
+    // the RTC may update between two register reads, so read until we get
+    // the same value twice in a row
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(RTC_STATUS_B);
+    let is_pm = raw.hour & HOUR_PM != 0;
+    let mut hour = raw.hour & !HOUR_PM;
+
+    if status_b & STATUS_B_BINARY == 0 {
+        raw.second = bcd_to_binary(raw.second);
+        raw.minute = bcd_to_binary(raw.minute);
+        hour = bcd_to_binary(hour);
+        raw.day = bcd_to_binary(raw.day);
+        raw.month = bcd_to_binary(raw.month);
+        raw.year = bcd_to_binary(raw.year);
+        raw.century = bcd_to_binary(raw.century);
+    }
+
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 hour mode: 12am is 0h and 12pm is 12h
+        hour %= 12;
+        if is_pm {
+            hour += 12;
+        }
+    }
+
+    // not every CMOS has a century register, assume we are past 2000
+    let century = if (19..=21).contains(&raw.century) {
+        raw.century as u16
+    } else {
+        20
+    };
+
+    DateTime {
+        year: century * 100 + raw.year as u16,
+        month: raw.month,
+        day: raw.day,
+        hour,
+        minute: raw.minute,
+        second: raw.second,
+    }
+}
@@ -1,24 +1,85 @@
 use goblin::elf::Elf;
 use crate::allocator::paging::PagingManager;
-use x86_64::{VirtAddr, structures::paging::{PageSize, Size4KiB, PageTableFlags, Page, page_table::PageTableEntry}};
+use x86_64::{VirtAddr, structures::paging::{Mapper, PageSize, Size4KiB, PageTableFlags, Page, page_table::PageTableEntry}};
 use core::alloc::Layout;
 use alloc::vec::Vec;
 use crate::libc::OsHandle;
 use crate::info;
+use crate::time;
 use crate::println_serial;
 use alloc::boxed::Box;
-use crate::thread::Process;
+use crate::thread::{self, oom, Personality, Process, ProcessMemoryContext, PROCESS_TABLE};
+use crate::thread::vma::{Backing, Protection, VmaKind};
+use crate::fs::{self, page_cache::FileRef, Path};
+use goblin::elf::program_header::ProgramHeader;
 
 #[derive(Debug)]
 pub enum ProgLoaderError {
     GoblinError(goblin::error::Error),
-    IsNotExe
+    IsNotExe,
+    // a segment points outside of the file or can't be mapped
+    InvalidSegment,
+    // even after the out of memory killer ran
+    OutOfMemory,
+}
+
+// auxiliary vector entries passed to linux programs
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+// where position independent programs are loaded, like static ones are linked
+const DYN_BASE: u64 = 0x400000;
+
+// splitmix64 finalizer, spreads every input bit over the whole word
+fn mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+// the 16 bytes of AT_RANDOM, libc seeds its stack canary with them. there is
+// no entropy source, so two reads of the cycle counter are mixed with the
+// timer ticks and the wall clock
+fn random_bytes() -> [u8; 16] {
+    let first = mix(unsafe { core::arch::x86_64::_rdtsc() } ^ time::ticks().rotate_left(32));
+    let second = mix(unsafe { core::arch::x86_64::_rdtsc() } ^ time::now() ^ first);
+
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&first.to_le_bytes());
+    bytes[8..].copy_from_slice(&second.to_le_bytes());
+    bytes
+}
+
+fn segment_protection(p_flags: u32) -> Protection {
+    use goblin::elf::program_header::{PF_R, PF_W, PF_X};
+
+    let mut prot = Protection::empty();
+    if p_flags & PF_R != 0 {
+        prot |= Protection::READ;
+    }
+    if p_flags & PF_W != 0 {
+        prot |= Protection::WRITE;
+    }
+    if p_flags & PF_X != 0 {
+        prot |= Protection::EXEC;
+    }
+    prot
 }
 
 #[derive(Debug)]
 pub struct ProgLoader<'a> {
     elf: Elf<'a>,
-    buffer: &'a [u8]
+    buffer: &'a [u8],
+    // file the program was read from, read only segments are mapped from it
+    source: Option<FileRef>,
+    // added to every address of the file, 0 unless it is position independent
+    base: u64,
 }
 
 
@@ -26,81 +87,252 @@ pub struct ProgLoader<'a> {
 impl<'a> ProgLoader<'a> {
     pub fn from_bytes(buffer: &'a [u8]) -> Result<Self, ProgLoaderError> {
         let elf = Elf::parse(buffer.as_ref()).map_err(ProgLoaderError::GoblinError)?;
+        let base = if elf.header.e_type == goblin::elf64::header::ET_DYN { DYN_BASE } else { 0 };
 
         Ok(Self {
             elf,
-            buffer
+            buffer,
+            source: None,
+            base,
         })
     }
 
-    pub fn map_memory(&mut self, paging_manager: &mut PagingManager) -> Option<()> {
-        for pheader in self.elf.program_headers.iter() {
-            if pheader.p_type == goblin::elf64::program_header::PT_LOAD {
-                let align = Size4KiB::SIZE;
-                let page_count = ((pheader.p_memsz + align  - 1) & !(align - 1)) / align;
-                let mut pages: Vec<Page> = Vec::new();
+    // the program comes from this file of the root filesystem, so its
+    // read only pages can be shared through the page cache
+    pub fn with_source(mut self, path: &str) -> Self {
+        self.source = fs::with_root(|fs| fs.metadata(Path::new(path)))
+            .ok()
+            .map(|metadata| FileRef { path: path.into(), inode: metadata.inode });
+        self
+    }
 
-                for i in 0..page_count {
-                    let frame = paging_manager.allocate_frame()?;
-                    let frame_addr = frame.start_address();
+    fn load_segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.elf.program_headers
+            .iter()
+            .filter(|pheader| pheader.p_type == goblin::elf64::program_header::PT_LOAD && pheader.p_memsz != 0)
+    }
 
-                    let virt_addr = VirtAddr::new(pheader.p_vaddr + i * align);
+    fn page_range(&self, pheader: &ProgramHeader) -> (VirtAddr, VirtAddr) {
+        let vaddr = VirtAddr::new(self.base + pheader.p_vaddr);
+        (vaddr.align_down(Size4KiB::SIZE), (vaddr + pheader.p_memsz).align_up(Size4KiB::SIZE))
+    }
 
-                    let flags = {
-                        let mut flags = PageTableFlags::WRITABLE | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    // read only segments laid out like in the file and alone on their pages
+    // can be mapped straight from the page cache instead of copied, unless
+    // a relocation has to patch them
+    fn can_map_from_file(&self, pheader: &ProgramHeader) -> bool {
+        let (start, end) = self.page_range(pheader);
+        let alone = self.load_segments()
+            .filter(|other| !core::ptr::eq(*other, pheader))
+            .all(|other| {
+                let (other_start, other_end) = self.page_range(other);
+                other_end <= start || end <= other_start
+            });
+        let relocated = self.elf.dynrelas
+            .iter()
+            .any(|reloc| (pheader.p_vaddr..pheader.p_vaddr + pheader.p_memsz).contains(&reloc.r_offset));
+
+        pheader.p_flags & goblin::elf::program_header::PF_W == 0
+            && pheader.p_offset % Size4KiB::SIZE == pheader.p_vaddr % Size4KiB::SIZE
+            && pheader.p_filesz == pheader.p_memsz
+            && alone
+            && !relocated
+    }
 
+    // every PT_LOAD segment becomes a program area of the process, written
+    // through the physical memory mapping, the heap starts after the last one
+    pub fn map_memory(&self, memory: &mut ProcessMemoryContext) -> Option<()> {
+        let mut program_end = VirtAddr::zero();
+
+        for pheader in self.load_segments() {
+            let vaddr = VirtAddr::new(self.base + pheader.p_vaddr);
+            let (first_page, end) = self.page_range(pheader);
+            let prot = segment_protection(pheader.p_flags);
+
+            if let Some(source) = self.source.as_ref().filter(|_| self.can_map_from_file(pheader)) {
+                let offset = pheader.p_offset - pheader.p_offset % Size4KiB::SIZE;
+                memory.map_file(
+                    first_page,
+                    end - first_page,
+                    prot,
+                    VmaKind::Program,
+                    Backing::File { file: source.clone(), offset, shared: false },
+                )?;
+                program_end = program_end.max(end);
+                continue;
+            }
 
-                        if pheader.is_executable() {
+            // two segments can share a page, it gets the rights of both
+            if first_page < program_end {
+                let shared_prot = memory.vmas().find(first_page)?.prot | prot;
+                memory.protect_region(first_page, Size4KiB::SIZE, shared_prot)?;
+            }
 
-                        }
+            let start = first_page.max(program_end);
+            if start < end {
+                memory.map_region(start, end - start, prot, VmaKind::Program, Backing::Anonymous)?;
+            }
 
-                        flags
-                    };
+            let poffset = pheader.p_offset as usize;
+            let pfilesz = pheader.p_filesz as usize;
+            let data = self.buffer.get(poffset..poffset + pfilesz)?;
+            memory.write_bytes(vaddr, data)?;
 
-                    paging_manager.map_memory(
-                        virt_addr, pheader.p_memsz as usize, frame_addr, flags
-                    );
-                    pages.push(Page::containing_address(virt_addr));
+            program_end = program_end.max(end);
+        }
+
+        memory.set_heap_start(program_end);
+        Some(())
+    }
+
+    // a static-pie program only has relative relocations, the address it
+    // was loaded at plus the addend. the program may apply them again itself,
+    // that writes the same values
+    fn relocate(&self, memory: &mut ProcessMemoryContext) -> Option<()> {
+        use goblin::elf::reloc::{R_X86_64_NONE, R_X86_64_RELATIVE};
+
+        for reloc in self.elf.dynrelas.iter() {
+            match reloc.r_type {
+                R_X86_64_NONE => {}
+                R_X86_64_RELATIVE => {
+                    let value = self.base.wrapping_add_signed(reloc.r_addend.unwrap_or(0));
+                    memory.write_bytes(VirtAddr::new(self.base + reloc.r_offset), &value.to_le_bytes())?;
                 }
+                _ => return None,
+            }
+        }
+        Some(())
+    }
+
+    fn entry(&self) -> u64 {
+        self.base + self.elf.header.e_entry
+    }
+
+    // binaries made by a linux toolchain are run with the linux syscall ABI
+    pub fn personality(&self) -> Personality {
+        let osabi = self.elf.header.e_ident[goblin::elf::header::EI_OSABI];
+        let has_gnu_stack = self.elf.program_headers
+            .iter()
+            .any(|pheader| pheader.p_type == goblin::elf::program_header::PT_GNU_STACK);
+
+        if osabi == goblin::elf::header::ELFOSABI_LINUX || has_gnu_stack {
+            Personality::Linux
+        } else {
+            Personality::Illuminos
+        }
+    }
 
+    fn program_headers_addr(&self) -> u64 {
+        if let Some(phdr) = self.elf.program_headers
+            .iter()
+            .find(|pheader| pheader.p_type == goblin::elf::program_header::PT_PHDR) {
+            return self.base + phdr.p_vaddr;
+        }
+
+        self.elf.program_headers
+            .iter()
+            .find(|pheader| pheader.p_type == goblin::elf64::program_header::PT_LOAD && pheader.p_offset == 0)
+            .map(|pheader| self.base + pheader.p_vaddr + self.elf.header.e_phoff)
+            .unwrap_or(0)
+    }
 
-                let poffset = pheader.p_offset as usize;
-                let pfilesz = pheader.p_filesz as usize;
-                let data = &self.buffer[poffset..poffset + pfilesz];
-                let raw_ptr = unsafe {
-                    pheader.p_vaddr as *mut u8
-                };
+    // initial stack expected by the linux ABI: argc, argv, envp and the
+    // auxiliary vector, we don't pass any argument or environment yet
+    fn setup_linux_stack(&self, process: &mut Process) -> Option<()> {
+        let mut sp = process.memory.stack_top();
+
+        sp -= 16;
+        let random_addr = sp;
+        process.memory.write_bytes(VirtAddr::new(random_addr), &random_bytes())?;
+
+        let mut words: Vec<u64> = Vec::new();
+        words.push(0); // argc
+        words.push(0); // argv
+        words.push(0); // envp
+        for (key, value) in [
+            (AT_PHDR, self.program_headers_addr()),
+            (AT_PHENT, self.elf.header.e_phentsize as u64),
+            (AT_PHNUM, self.elf.header.e_phnum as u64),
+            (AT_PAGESZ, Size4KiB::SIZE),
+            (AT_ENTRY, self.entry()),
+            (AT_RANDOM, random_addr),
+            (AT_NULL, 0),
+        ] {
+            words.push(key);
+            words.push(value);
+        }
+
+        // rsp has to be 16 bytes aligned at the entry point
+        sp &= !0xF;
+        if words.len() % 2 == 1 {
+            sp -= 8;
+        }
+        sp -= (words.len() * 8) as u64;
 
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        process.memory.write_bytes(VirtAddr::new(sp), &bytes)?;
+        process.memory.set_user_rsp(sp);
 
-                unsafe {
-                    raw_ptr.copy_from(data.as_ptr(), data.len());
+        Some(())
+    }
+
+    // new process with the program loaded, what was mapped is given back
+    // when it fails
+    fn load(&self, paging_manager: &mut PagingManager<'static>) -> Result<Process<'static>, ProgLoaderError> {
+        let page_table = Process::create_user_page_table(paging_manager).ok_or(ProgLoaderError::OutOfMemory)?;
+        let mut process = Process::spawn_user(
+            page_table,
+            thread::USER_STACK_SIZE,
+            VirtAddr::new(self.entry()),
+            paging_manager
+        ).ok_or(ProgLoaderError::OutOfMemory)?;
+
+        let personality = self.personality();
+        process.set_personality(personality);
+
+        let loaded = self.map_memory(&mut process.memory)
+            .and_then(|_| self.relocate(&mut process.memory))
+            .and_then(|_| {
+                if personality == Personality::Linux {
+                    self.setup_linux_stack(&mut process)
+                } else {
+                    Some(())
                 }
-            }
+            });
+
+        if loaded.is_none() {
+            let out_of_memory = process.memory.take_out_of_frames();
+            process.memory.release();
+            return Err(if out_of_memory { ProgLoaderError::OutOfMemory } else { ProgLoaderError::InvalidSegment });
         }
 
-        return Some(());
+        Ok(process)
     }
 
-    pub fn execute(&mut self, paging_manager: &mut PagingManager) -> Result<(), ProgLoaderError> {
-        if self.elf.header.e_type != goblin::elf64::header::ET_EXEC {
+    pub fn execute(&mut self, paging_manager: &mut PagingManager<'static>) -> Result<(), ProgLoaderError> {
+        if self.elf.header.e_type != goblin::elf64::header::ET_EXEC
+            && self.elf.header.e_type != goblin::elf64::header::ET_DYN
+        {
+            return Err(ProgLoaderError::IsNotExe)
+        }
+        // there is no dynamic linker to hand the program to
+        if self.elf.interpreter.is_some() {
             return Err(ProgLoaderError::IsNotExe)
         }
 
-        self.map_memory(paging_manager);
-
-        println_serial!("{:?}", paging_manager.mapper.phys_offset());
+        let process = loop {
+            match self.load(paging_manager) {
+                Ok(process) => break process,
+                // memory comes back from the largest process, if there is one
+                Err(ProgLoaderError::OutOfMemory) if oom::out_of_memory() => continue,
+                Err(error) => return Err(error),
+            }
+        };
 
-        let mut res = Process::create_user_page_table(paging_manager).unwrap();
-        let mut process =  Process::spawn_user(
-            res,
-            1024 * 1024,
-            1024 * 1024 * 10,
-            VirtAddr::new(self.elf.header.e_entry),
-            paging_manager
-        ).unwrap();
+        let pid = PROCESS_TABLE.lock().add(process);
 
         unsafe {
-            process.execute();
+            thread::run(pid);
         };
 
 /*
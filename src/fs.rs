@@ -1,15 +1,18 @@
+pub mod devfs;
 pub mod ext2;
 pub mod fat32;
+pub mod page_cache;
+pub mod procfs;
 
 use alloc::{
+    boxed::Box,
     string::{String, ToString},
+    sync::Arc,
     vec::Vec,
 };
 use bitflags::bitflags;
-use crate::io::port::*;
-
-use core::cell::RefCell;
-use alloc::rc::Rc;
+use spin::Mutex;
+use crate::io::port::{self, Device, DeviceError, Fd};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Error {
@@ -19,6 +22,7 @@ pub enum Error {
     NotADirectory,
     NotAFile,
     NotEnoughSpace,
+    AlreadyExists,
     KernelError(String),
 }
 
@@ -29,56 +33,306 @@ pub trait FileSystem where Self: 'static {
     fn rename(&mut self, old_path: Path, new_path: Path) -> Result<(), Error>;
     fn create_directory(&mut self, path: Path) -> Result<(), Error>;
     fn create_file(&mut self, path: Path) -> Result<(), Error>;
+    fn metadata(&mut self, path: Path) -> Result<Metadata, Error>;
+    fn read_dir(&mut self, path: Path) -> Result<Vec<DirEntry>, Error>;
     fn is_exist(&mut self, path: Path) -> bool {
         return self.read(path).is_ok();
     }
 
-    fn open(fs: Rc<RefCell<Self>>, path: &'static str, flags: OpenFlags) -> Result<File, Error> {
-        let fs_clone = Rc::clone(&fs);
+    // the defaults go through the whole file, filesystems override them to
+    // only touch the blocks under the range
+    fn read_at(&mut self, path: Path, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        let data = self.read(path)?;
+        Ok(read_slice(&data, buf, offset))
+    }
 
-        if !fs_clone.borrow_mut().is_exist(Path::new(path)) {
-            return Err(Error::FileNotFound);
+    fn write_at(&mut self, path: Path, buf: &[u8], offset: u64) -> Result<usize, Error> {
+        let start = offset as usize;
+        let end = start.checked_add(buf.len()).ok_or(Error::NotEnoughSpace)?;
+
+        let mut data = self.read(path.clone())?;
+        if data.len() < end {
+            data.resize(end, 0);
         }
+        data[start..end].copy_from_slice(buf);
+        self.write(path, &data)?;
+        Ok(buf.len())
+    }
+
+    fn set_len(&mut self, path: Path, len: u64) -> Result<(), Error> {
+        let mut data = self.read(path.clone())?;
+        data.resize(len as usize, 0);
+        self.write(path, &data)
+    }
 
-        let read_clone = Rc::clone(&fs);
-        let write_clone = Rc::clone(&fs);
+    fn open(fs: Arc<Mutex<Self>>, path: impl Into<Path>, flags: OpenFlags) -> Result<File, Error>
+    where
+        Self: Sized + Send,
+    {
+        let path = path.into();
+        prepare_open(&mut *fs.lock(), &path, flags)?;
 
         Ok(
             File {
-                fd: new_port(move || read_clone.borrow_mut().read(Path::new(path)).unwrap(),
-                             move |buf| write_clone.borrow_mut().write(Path::new(path), buf).unwrap()),
-                flag: flags
+                fd: port::register(Arc::new(FileDevice { fs: Some(fs), path })),
+                flag: flags,
+                pos: 0,
             }
         )
 
     }
 }
 
+// CREATE, EXCL and TRUNCATE, before the file is opened
+fn prepare_open(fs: &mut dyn FileSystem, path: &Path, flags: OpenFlags) -> Result<(), Error> {
+    let exists = fs.is_exist(path.clone());
+    if exists && flags.contains(OpenFlags::CREATE | OpenFlags::EXCL) {
+        return Err(Error::AlreadyExists);
+    }
+    if !exists {
+        if !flags.contains(OpenFlags::CREATE) {
+            return Err(Error::FileNotFound);
+        }
+        return fs.create_file(path.clone());
+    }
+
+    // posix leaves truncating a read only file undefined, it is left alone
+    if flags.contains(OpenFlags::TRUNCATE | OpenFlags::WRITE) {
+        fs.set_len(path.clone(), 0)?;
+    }
+    Ok(())
+}
+
+// copies what `data` has at `offset` into `buf`
+fn read_slice(data: &[u8], buf: &mut [u8], offset: u64) -> usize {
+    let start = (offset as usize).min(data.len());
+    let len = buf.len().min(data.len() - start);
+    buf[..len].copy_from_slice(&data[start..start + len]);
+    len
+}
+
+// device of an open file
+struct FileDevice {
+    // None for the root filesystem
+    fs: Option<Arc<Mutex<dyn FileSystem + Send>>>,
+    path: Path,
+}
+
+impl FileDevice {
+    fn with_fs<R>(&self, f: impl FnOnce(&mut dyn FileSystem) -> Result<R, Error>) -> Result<R, Error> {
+        match &self.fs {
+            Some(fs) => f(&mut *fs.lock()),
+            None => with_root(f),
+        }
+    }
+}
+
+impl Device for FileDevice {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, DeviceError> {
+        self.with_fs(|fs| fs.read_at(self.path.clone(), buf, offset)).map_err(|_| DeviceError::Io)
+    }
+
+    fn write(&self, buf: &[u8], offset: u64) -> Result<usize, DeviceError> {
+        self.with_fs(|fs| fs.write_at(self.path.clone(), buf, offset)).map_err(|_| DeviceError::Io)
+    }
+
+    fn size(&self) -> Result<u64, DeviceError> {
+        self.with_fs(|fs| fs.metadata(self.path.clone()))
+            .map(|metadata| metadata.size)
+            .map_err(|_| DeviceError::Io)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), DeviceError> {
+        self.with_fs(|fs| fs.set_len(self.path.clone(), len)).map_err(|_| DeviceError::Io)
+    }
+}
+
+// /proc files are generated when read
+struct ProcDevice {
+    path: String,
+}
+
+impl Device for ProcDevice {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, DeviceError> {
+        let data = procfs::read(&self.path).ok_or(DeviceError::Io)?;
+        Ok(read_slice(&data, buf, offset))
+    }
+
+    fn write(&self, _buf: &[u8], _offset: u64) -> Result<usize, DeviceError> {
+        Err(DeviceError::NotSupported)
+    }
+
+    fn size(&self) -> Result<u64, DeviceError> {
+        procfs::read(&self.path).map(|data| data.len() as u64).ok_or(DeviceError::Io)
+    }
+}
+
+
+pub static ROOT_FS: Mutex<Option<Box<dyn FileSystem + Send>>> = Mutex::new(None);
+
+pub fn mount_root<F: FileSystem + Send>(fs: F) {
+    *ROOT_FS.lock() = Some(Box::new(fs));
+}
+
+pub fn with_root<R, F>(f: F) -> Result<R, Error>
+where
+    F: FnOnce(&mut dyn FileSystem) -> Result<R, Error>,
+{
+    let mut root = ROOT_FS.lock();
+    let fs = root.as_mut().ok_or(Error::RootDirNotFound)?;
+    f(fs.as_mut())
+}
+
+// open a file of the root filesystem, the returned file is backed by a port
+pub fn open(path: impl Into<Path>, flags: OpenFlags) -> Result<File, Error> {
+    let path = path.into();
+
+    // /proc files are read only and never reach the root filesystem
+    let name = path.to_string();
+    if procfs::exists(&name) {
+        if flags.contains(OpenFlags::CREATE | OpenFlags::EXCL) {
+            return Err(Error::AlreadyExists);
+        }
+        return Ok(
+            File {
+                fd: port::register(Arc::new(ProcDevice { path: name })),
+                flag: flags - OpenFlags::WRITE - OpenFlags::APPEND,
+                pos: 0,
+            }
+        );
+    }
+
+    with_root(|fs| prepare_open(fs, &path, flags))?;
+
+    Ok(
+        File {
+            fd: port::register(Arc::new(FileDevice { fs: None, path })),
+            flag: flags,
+            pos: 0,
+        }
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub inode: u64,
+    pub kind: FileKind,
+    pub size: u64,
+    pub perm: u16,
+    pub access_time: u64,
+    pub modif_time: u64,
+    pub creation_time: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub inode: u64,
+    pub name: String,
+    pub kind: FileKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
 
 #[derive(Debug)]
 pub struct File {
     fd: Fd,
-    flag: OpenFlags
+    flag: OpenFlags,
+    // where read and write start
+    pos: u64,
+}
+
+// the port of a file is closed with it
+impl Drop for File {
+    fn drop(&mut self) {
+        port::unregister(self.fd.fd());
+    }
 }
 
 impl File {
-    pub fn read(&self) -> Option<Vec<u8>> {
+    pub fn fd(&self) -> &Fd {
+        &self.fd
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flag
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn len(&self) -> Result<u64, DeviceError> {
+        self.fd.size()
+    }
+
+    // reads at the cursor and moves it past what was read
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+        let len = self.read_at(buf, self.pos)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    // writes at the cursor, or at the end with APPEND, and moves it past what
+    // was written
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, DeviceError> {
+        if self.flag.contains(OpenFlags::APPEND) {
+            self.pos = self.len()?;
+        }
+
+        let len = self.write_at(buf, self.pos)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    // the cursor may go past the end, a write there leaves zeros in between
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64, DeviceError> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(pos) => (pos, 0),
+            SeekFrom::End(offset) => (self.len()?, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+
+        self.pos = base.checked_add_signed(offset).ok_or(DeviceError::InvalidArgument)?;
+        Ok(self.pos)
+    }
+
+    // the cursor doesn't move
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, DeviceError> {
         if !self.flag.contains(OpenFlags::READ) {
-            return None;
+            return Err(DeviceError::NotSupported);
         }
 
-        self.fd.read()
+        self.fd.read(buf, offset)
     }
 
-    #[must_use]
-    pub fn write(&self, buf: &[u8]) -> Option<()> {
+    // the cursor doesn't move and APPEND isn't looked at
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, DeviceError> {
         if !self.flag.contains(OpenFlags::WRITE) {
-            return None;
+            return Err(DeviceError::NotSupported);
         }
 
-        self.fd.write(buf);
+        self.fd.write(buf, offset)
+    }
+
+    // cuts or extends the file with zeros, the cursor doesn't move
+    pub fn set_len(&self, len: u64) -> Result<(), DeviceError> {
+        if !self.flag.contains(OpenFlags::WRITE) {
+            return Err(DeviceError::NotSupported);
+        }
 
-        Some(())
+        self.fd.set_len(len)
     }
 }
 
@@ -93,6 +347,12 @@ bitflags! {
         const WRITE = 1 << 1;
         const APPEND = 1 << 2;
         const BINARY = 1 << 3;
+        // the file is created when missing
+        const CREATE = 1 << 4;
+        // a file opened for writing is emptied
+        const TRUNCATE = 1 << 5;
+        // with CREATE, fails when the file exists
+        const EXCL = 1 << 6;
     }
 }
 
@@ -108,6 +368,18 @@ pub struct Path {
     absolute: bool,
 }
 
+impl From<&str> for Path {
+    fn from(path: &str) -> Self {
+        Path::new(path)
+    }
+}
+
+impl From<String> for Path {
+    fn from(path: String) -> Self {
+        Path::new(&path)
+    }
+}
+
 impl Path {
     pub fn new(path: &str) -> Self {
         let absolute = path.starts_with('/');
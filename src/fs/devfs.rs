@@ -0,0 +1,20 @@
+// device files under /dev, each names a port registered by the kernel
+//
+// opening one shares the port instead of making a file, closing it leaves
+// the device registered
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use spin::Mutex;
+
+static DEVICES: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+// `name` shows up as /dev/<name>
+pub fn register(name: &str, port: usize) {
+    DEVICES.lock().insert(format!("/dev/{}", name), port);
+}
+
+pub fn port(path: &str) -> Option<usize> {
+    DEVICES.lock().get(path).copied()
+}
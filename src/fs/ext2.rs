@@ -5,7 +5,7 @@ use alloc::vec::Vec;
 use alloc::vec;
 
 use crate::drivers::disk::Disk;
-use crate::{info, math};
+use crate::{info, math, time};
 
 #[derive(Debug, Clone, Copy, Default)]
 #[repr(C)]
@@ -663,26 +663,286 @@ where
     }
 
     pub fn read_file(&mut self, inode: Inode) -> Result<Vec<u8>, super::Error> {
-        let block_size = self.super_block.block_size();
-        let filesize = inode.size_low; // in byte
-        let mut remaining_size = filesize;
-        let mut data = Vec::new();
-        let mut current_dbp = 0;
+        let mut data = vec![0; inode.size_low as usize];
+        self.read_inode_at(&inode, &mut data, 0);
 
-        while remaining_size != 0 {
-            let current_block = self.read_block(inode.dbp[current_dbp]);
-            if remaining_size < block_size {
-                data.extend_from_slice(&current_block[..remaining_size as usize]);
-                break;
+        Ok(data)
+    }
+
+    // logical block `index` of a file as the inode slot it hangs from (0-11
+    // direct, 12-14 indirect) and the entry to follow in each indirect block
+    // below that slot
+    fn block_path(&self, index: u64) -> Option<(usize, Vec<u32>)> {
+        if index < 12 {
+            return Some((index as usize, Vec::new()));
+        }
+
+        let per_block = (self.super_block.block_size() / 4) as u64;
+        let mut index = index - 12;
+        for (slot, level) in [(12, 1), (13, 2), (14, 3)] {
+            let count = per_block.pow(level);
+            if index < count {
+                let path = (0..level)
+                    .rev()
+                    .map(|depth| (index / per_block.pow(depth) % per_block) as u32)
+                    .collect();
+                return Some((slot, path));
+            }
+            index -= count;
+        }
+
+        None
+    }
+
+    // block holding logical block `index` of the file, 0 for a hole
+    pub fn data_block(&mut self, inode: &Inode, index: u64) -> u32 {
+        let Some((slot, path)) = self.block_path(index) else {
+            return 0;
+        };
+
+        let mut block = *inode_slot(inode, slot);
+        for entry in path {
+            if block == 0 {
+                return 0;
+            }
+            block = read_u32(&self.read_block(block), entry as usize);
+        }
+        block
+    }
+
+    // like data_block, the missing data and indirect blocks are allocated
+    // zeroed. the caller writes the inode back
+    fn data_block_or_allocate(&mut self, inode: &mut Inode, index: u64) -> Result<u32, super::Error> {
+        let (slot, path) = self.block_path(index).ok_or(super::Error::NotEnoughSpace)?;
+
+        let mut block = *inode_slot(inode, slot);
+        if block == 0 {
+            block = self.allocate_zeroed_block()?;
+            *inode_slot_mut(inode, slot) = block;
+        }
+
+        for entry in path {
+            let mut table = self.read_block(block);
+            let mut next = read_u32(&table, entry as usize);
+            if next == 0 {
+                next = self.allocate_zeroed_block()?;
+                write_u32(&mut table, entry as usize, next);
+                self.write_block(block, &table);
+            }
+            block = next;
+        }
+
+        Ok(block)
+    }
+
+    fn allocate_zeroed_block(&mut self) -> Result<u32, super::Error> {
+        let block = self.allocate_block().ok_or(super::Error::NotEnoughSpace)?;
+        // write_block pads with zeros
+        self.write_block(block, &[]);
+        Ok(block)
+    }
+
+    // inverse of allocate_block
+    pub fn free_block(&mut self, block_id: u32) {
+        let blocks_per_group = self.super_block.block_group_count;
+        let group = (block_id / blocks_per_group) as usize;
+        let index = (block_id % blocks_per_group) as usize;
+        let Some(bitmap_block) = self.bgd.get(group).map(|bgd| bgd.block_bitmap_addr) else {
+            return;
+        };
+
+        let mut bitmap = self.read_block(bitmap_block);
+        if bitmap[index / 8] & (1 << (index % 8)) != 0 {
+            bitmap[index / 8] &= !(1 << (index % 8));
+            self.write_block(bitmap_block, &bitmap);
+            self.bgd[group].unallocated_block_count += 1;
+        }
+    }
+
+    // frees the blocks under the indirect block `block`, `level` deep, that
+    // hold logical blocks from `first` on, counted from the start of the
+    // tree. true when `block` itself was freed
+    fn free_tree(&mut self, block: u32, level: u32, first: u64) -> bool {
+        let per_block = (self.super_block.block_size() / 4) as u64;
+        let span = per_block.pow(level - 1);
+        let mut table = self.read_block(block);
+        let mut changed = false;
+
+        for i in 0..per_block {
+            let entry = read_u32(&table, i as usize);
+            if entry == 0 || (i + 1) * span <= first {
+                continue;
+            }
+
+            let freed = if level == 1 {
+                self.free_block(entry);
+                true
             } else {
-                data.extend(current_block);
-                remaining_size -= block_size;
-                current_dbp += 1;
+                self.free_tree(entry, level - 1, first.saturating_sub(i * span))
+            };
+            if freed {
+                write_u32(&mut table, i as usize, 0);
+                changed = true;
             }
         }
 
+        if first == 0 {
+            self.free_block(block);
+            return true;
+        }
+        if changed {
+            self.write_block(block, &table);
+        }
+        false
+    }
 
-        Ok(data)
+    // frees logical blocks from `first` on
+    fn free_blocks_from(&mut self, inode: &mut Inode, first: u64) {
+        for slot in 0..12 {
+            if slot as u64 >= first && inode.dbp[slot] != 0 {
+                self.free_block(inode.dbp[slot]);
+                inode.dbp[slot] = 0;
+            }
+        }
+
+        let per_block = (self.super_block.block_size() / 4) as u64;
+        let mut base = 12;
+        for (slot, level) in [(12, 1), (13, 2), (14, 3)] {
+            let block = *inode_slot(inode, slot);
+            if block != 0 && self.free_tree(block, level, first.saturating_sub(base)) {
+                *inode_slot_mut(inode, slot) = 0;
+            }
+            base += per_block.pow(level);
+        }
+    }
+
+    // zeros the last block of the file past `size`, so a file growing over
+    // it reads zeros there
+    fn zero_tail(&mut self, inode: &Inode, size: u64) {
+        let block_size = self.super_block.block_size() as u64;
+        if size.is_multiple_of(block_size) {
+            return;
+        }
+
+        let block = self.data_block(inode, size / block_size);
+        if block != 0 {
+            let mut data = self.read_block(block);
+            data[(size % block_size) as usize..].fill(0);
+            self.write_block(block, &data);
+        }
+    }
+
+    // bytes of the file at `offset`, fewer at the end of the file. holes
+    // read as zeros
+    pub fn read_inode_at(&mut self, inode: &Inode, buf: &mut [u8], offset: u64) -> usize {
+        let size = inode.size_low as u64;
+        if offset >= size {
+            return 0;
+        }
+
+        let block_size = self.super_block.block_size() as u64;
+        let len = buf.len().min((size - offset) as usize);
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let start = (pos % block_size) as usize;
+            let chunk = (block_size as usize - start).min(len - done);
+            let dst = &mut buf[done..done + chunk];
+
+            match self.data_block(inode, pos / block_size) {
+                0 => dst.fill(0),
+                block => dst.copy_from_slice(&self.read_block(block)[start..start + chunk]),
+            }
+            done += chunk;
+        }
+
+        len
+    }
+
+    // only the blocks under the range are read and written. on a full disk
+    // what fit is kept and its length returned
+    pub fn write_inode_at(&mut self, inode_number: u32, buf: &[u8], offset: u64) -> Result<usize, super::Error> {
+        let mut inode = self.read_inode(inode_number).ok_or(super::Error::FileNotFound)?;
+        if inode.is_dir() {
+            return Err(super::Error::NotAFile);
+        }
+        // the size is 32 bits
+        offset
+            .checked_add(buf.len() as u64)
+            .filter(|end| *end <= u32::MAX as u64)
+            .ok_or(super::Error::NotEnoughSpace)?;
+
+        let size = inode.size_low as u64;
+        if offset > size {
+            self.zero_tail(&inode, size);
+        }
+
+        let block_size = self.super_block.block_size() as u64;
+        let mut done = 0;
+        let mut error = None;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let start = (pos % block_size) as usize;
+            let chunk = (block_size as usize - start).min(buf.len() - done);
+
+            let block = match self.data_block_or_allocate(&mut inode, pos / block_size) {
+                Ok(block) => block,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            };
+
+            // a whole block doesn't need its old content
+            let mut data = if chunk == block_size as usize {
+                vec![0; chunk]
+            } else {
+                self.read_block(block)
+            };
+            data[start..start + chunk].copy_from_slice(&buf[done..done + chunk]);
+            self.write_block(block, &data);
+            done += chunk;
+        }
+
+        if done > 0 {
+            inode.size_low = size.max(offset + done as u64) as u32;
+        }
+        inode.last_modif_time = time::now() as u32;
+        self.write_inode(inode_number, inode)?;
+        self.flush();
+
+        match error {
+            Some(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    // cuts or extends the file, the blocks past the new end are freed and
+    // the new bytes read as zeros
+    pub fn set_inode_len(&mut self, inode_number: u32, len: u64) -> Result<(), super::Error> {
+        let mut inode = self.read_inode(inode_number).ok_or(super::Error::FileNotFound)?;
+        if inode.is_dir() {
+            return Err(super::Error::NotAFile);
+        }
+        if len > u32::MAX as u64 {
+            return Err(super::Error::NotEnoughSpace);
+        }
+
+        let size = inode.size_low as u64;
+        if len < size {
+            let block_size = self.super_block.block_size() as u64;
+            self.free_blocks_from(&mut inode, len.div_ceil(block_size));
+            self.zero_tail(&inode, len);
+        } else if len > size {
+            self.zero_tail(&inode, size);
+        }
+
+        inode.size_low = len as u32;
+        inode.last_modif_time = time::now() as u32;
+        self.write_inode(inode_number, inode)?;
+        self.flush();
+
+        Ok(())
     }
 
     pub fn read_directory(&mut self, inode: u32) -> Result<Vec<DirectoryEntry>, super::Error> {
@@ -848,9 +1108,17 @@ where
         &mut self,
         parent_inode_number: u32,
         name: &str,
-        inode: Inode,
+        mut inode: Inode,
     ) -> Result<(), super::Error> {
         let mut parent_inode = self.read_inode(parent_inode_number).ok_or(super::Error::FileNotFound)?;
+        let now = time::now() as u32;
+        if inode.creation_time == 0 {
+            inode.creation_time = now;
+            inode.last_modif_time = now;
+            inode.last_access_time = now;
+        }
+        parent_inode.last_modif_time = now;
+        parent_inode.last_access_time = now;
         let mut dir = self.read_directory(parent_inode_number)?;
         let inode_id = self.allocate_inode().ok_or(super::Error::NotEnoughSpace)?;
         let mut new_entry = DirectoryEntry {
@@ -952,9 +1220,17 @@ where
         &mut self,
         parent_inode_number: u32,
         name: &str,
-        inode: Inode,
+        mut inode: Inode,
     ) -> Result<(), super::Error> {
         let mut parent_inode = self.read_inode(parent_inode_number).ok_or(super::Error::FileNotFound)?;
+        let now = time::now() as u32;
+        if inode.creation_time == 0 {
+            inode.creation_time = now;
+            inode.last_modif_time = now;
+            inode.last_access_time = now;
+        }
+        parent_inode.last_modif_time = now;
+        parent_inode.last_access_time = now;
         let inode_id = self.allocate_inode().ok_or(super::Error::NotEnoughSpace)?;
         let mut new_entry = DirectoryEntry {
             inode: inode_id,
@@ -1081,9 +1357,10 @@ where
         let mut inode = Inode::new();
         inode.type_and_perm = INODE_TYPE_REG | 0o644;
         inode.size_low = data.len() as u32;
-        inode.last_access_time = 1744108895;
-        inode.last_modif_time = 1744108895;
-        inode.creation_time = 1744108895;
+        let now = time::now() as u32;
+        inode.last_access_time = now;
+        inode.last_modif_time = now;
+        inode.creation_time = now;
         let mut remaining_size = data.len() as u32;
         let mut current_dbp = 0;
         while remaining_size != 0 {
@@ -1231,6 +1508,33 @@ where
     }
 }
 
+fn inode_slot(inode: &Inode, slot: usize) -> &u32 {
+    match slot {
+        0..12 => &inode.dbp[slot],
+        12 => &inode.sibp,
+        13 => &inode.dibp,
+        _ => &inode.tibp,
+    }
+}
+
+fn inode_slot_mut(inode: &mut Inode, slot: usize) -> &mut u32 {
+    match slot {
+        0..12 => &mut inode.dbp[slot],
+        12 => &mut inode.sibp,
+        13 => &mut inode.dibp,
+        _ => &mut inode.tibp,
+    }
+}
+
+// entries of an indirect block
+fn read_u32(block: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes(block[index * 4..index * 4 + 4].try_into().unwrap())
+}
+
+fn write_u32(block: &mut [u8], index: usize, value: u32) {
+    block[index * 4..index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+}
+
 impl<T: Disk + Clone + 'static> super::FileSystem for Ext2FS<T> {
     fn read(&mut self, path: super::Path) -> Result<Vec<u8>, super::Error> {
         let inode_id = self.read_path(path)?;
@@ -1240,8 +1544,40 @@ impl<T: Disk + Clone + 'static> super::FileSystem for Ext2FS<T> {
         Ok(file)
     }
 
+    // an existing file is rewritten in place
     fn write(&mut self, path: super::Path, data: &[u8]) -> Result<(), super::Error> {
-        self.write_file_path(path, data)
+        let inode_id = match self.read_path(path.clone()) {
+            Ok(inode_id) => inode_id,
+            Err(super::Error::FileNotFound) => return self.write_file_path(path, data),
+            Err(e) => return Err(e),
+        };
+
+        self.set_inode_len(inode_id, 0)?;
+        if self.write_inode_at(inode_id, data, 0)? < data.len() {
+            return Err(super::Error::NotEnoughSpace);
+        }
+        Ok(())
+    }
+
+    fn is_exist(&mut self, path: super::Path) -> bool {
+        self.read_path(path).is_ok()
+    }
+
+    fn read_at(&mut self, path: super::Path, buf: &mut [u8], offset: u64) -> Result<usize, super::Error> {
+        let inode_id = self.read_path(path)?;
+        let inode = self.read_inode(inode_id).ok_or(super::Error::FileNotFound)?;
+
+        Ok(self.read_inode_at(&inode, buf, offset))
+    }
+
+    fn write_at(&mut self, path: super::Path, buf: &[u8], offset: u64) -> Result<usize, super::Error> {
+        let inode_id = self.read_path(path)?;
+        self.write_inode_at(inode_id, buf, offset)
+    }
+
+    fn set_len(&mut self, path: super::Path, len: u64) -> Result<(), super::Error> {
+        let inode_id = self.read_path(path)?;
+        self.set_inode_len(inode_id, len)
     }
 
     fn delete(&mut self, path: super::Path) -> Result<(), super::Error> {
@@ -1257,6 +1593,54 @@ impl<T: Disk + Clone + 'static> super::FileSystem for Ext2FS<T> {
     }
 
     fn create_file(&mut self, path: super::Path) -> Result<(), super::Error> {
-        todo!()
+        if self.read_path(path.clone()).is_ok() {
+            return Err(super::Error::AlreadyExists);
+        }
+
+        self.write_file_path(path, &[])
+    }
+
+    fn metadata(&mut self, path: super::Path) -> Result<super::Metadata, super::Error> {
+        let inode_id = self.read_path(path)?;
+        let inode = self.read_inode(inode_id).ok_or(super::Error::FileNotFound)?;
+
+        Ok(super::Metadata {
+            inode: inode_id as u64,
+            kind: if inode.is_dir() {
+                super::FileKind::Directory
+            } else if inode.is_file() {
+                super::FileKind::File
+            } else {
+                super::FileKind::Other
+            },
+            size: inode.size_low as u64,
+            perm: inode.perm(),
+            access_time: inode.last_access_time as u64,
+            modif_time: inode.last_modif_time as u64,
+            creation_time: inode.creation_time as u64,
+        })
+    }
+
+    fn read_dir(&mut self, path: super::Path) -> Result<Vec<super::DirEntry>, super::Error> {
+        let inode_id = self.read_path(path)?;
+        let inode = self.read_inode(inode_id).ok_or(super::Error::FileNotFound)?;
+        if !inode.is_dir() {
+            return Err(super::Error::NotADirectory);
+        }
+
+        let entries = self.read_directory(inode_id)?;
+
+        Ok(entries
+            .iter()
+            .map(|entry| super::DirEntry {
+                inode: entry.inode as u64,
+                name: entry.name().unwrap_or("").to_string(),
+                kind: match entry.file_type() {
+                    Some(DirTypeIndicator::RegFile) => super::FileKind::File,
+                    Some(DirTypeIndicator::Dir) => super::FileKind::Directory,
+                    _ => super::FileKind::Other,
+                },
+            })
+            .collect())
     }
 }
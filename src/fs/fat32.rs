@@ -5,7 +5,7 @@ use alloc::vec;
 use alloc::vec::Vec;
 
 use crate::drivers::disk::ata::AtaPio;
-use crate::info;
+use crate::{info, time};
 
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
@@ -74,6 +74,24 @@ impl DirectoryEntry {
     }
 }
 
+// fat entry ending a chain, every value from 0x0FFFFFF8 up does
+const FAT_END: u32 = 0x0FFFFFFF;
+
+fn is_chain_end(entry: u32) -> bool {
+    entry == 0 || entry & 0x0FFFFFFF >= 0x0FFFFFF8
+}
+
+// a name like "HELLO   TXT" is matched against "hello.txt"
+fn name_matches(entry: &DirectoryEntry, filename: &str) -> bool {
+    let mut name = entry.name.to_vec();
+    name.retain(|&x| x != 0x20);
+    let name = core::str::from_utf8(&name).unwrap_or("").trim();
+    let mut parts = filename.splitn(2, '.');
+    let base_name = parts.next().unwrap_or("");
+    let extension = parts.next().unwrap_or("");
+    name == base_name.to_string() + extension
+}
+
 pub struct FAT32 {
     pub boot_sector: BootSector,
     pub fat_start: u32,
@@ -114,8 +132,13 @@ impl FAT32 {
         final_buffer
     }
 
+    // sector of the fat holding the entry of `cluster`
+    fn fat_sector(&self, cluster: u32) -> u32 {
+        self.fat_start + cluster * 4 / 512
+    }
+
     pub fn read_fat_entry(&mut self, ata: &mut AtaPio, cluster: u32) -> u32 {
-        let fat_sector = self.cluster_to_sector(cluster);
+        let fat_sector = self.fat_sector(cluster);
         let mut buffer = [0u8; 512];
         ata.read_sector8(fat_sector, &mut buffer);
         let offset = (cluster * 4) % 512;
@@ -127,7 +150,7 @@ impl FAT32 {
     }
 
     pub fn write_fat_entry(&mut self, ata: &mut AtaPio, cluster: u32, value: u32) {
-        let fat_sector = self.cluster_to_sector(cluster);
+        let fat_sector = self.fat_sector(cluster);
         let mut buffer = [0u8; 512];
         ata.read_sector8(fat_sector, &mut buffer);
         let offset = (cluster * 4) % 512;
@@ -176,6 +199,7 @@ impl FAT32 {
         name: &str,
         is_directory: bool,
     ) -> Option<DirectoryEntry> {
+        let now = time::now_datetime();
         let mut buffer = [0u8; 512];
         let sector = self.data_start + (dir_cluster - 2) * self.cluster_size;
         ata.read_sector8(sector, &mut buffer);
@@ -187,12 +211,12 @@ impl FAT32 {
                     name: [0x20; 11],
                     attr: if is_directory { 0x10 } else { 0x20 },
                     reserved: 0,
-                    ctime: 0,
-                    cdate: 0,
-                    adate: 0,
+                    ctime: now.fat_time(),
+                    cdate: now.fat_date(),
+                    adate: now.fat_date(),
                     first_cluster_high: 0,
-                    mtime: 0,
-                    mdate: 0,
+                    mtime: now.fat_time(),
+                    mdate: now.fat_date(),
                     first_cluster_low: 0,
                     size: 0,
                     ctime_tenth: 0,
@@ -233,17 +257,19 @@ impl FAT32 {
     }
 
     pub fn init_directory(&mut self, ata: &mut AtaPio, dir_cluster: u32, root_cluser: u32) {
+        let now = time::now_datetime();
+
         // . dir
         let mut dot_entry = DirectoryEntry {
             name: [0x20; 11],
             attr: 0x10,
             reserved: 0,
-            ctime: 0,
-            cdate: 0,
-            adate: 0,
+            ctime: now.fat_time(),
+            cdate: now.fat_date(),
+            adate: now.fat_date(),
             first_cluster_high: (dir_cluster >> 16) as u16,
-            mtime: 0,
-            mdate: 0,
+            mtime: now.fat_time(),
+            mdate: now.fat_date(),
             ctime_tenth: 0,
             first_cluster_low: (dir_cluster & 0xFFFF) as u16,
             size: 0,
@@ -255,12 +281,12 @@ impl FAT32 {
             name: [0x20; 11],
             attr: 0x10,
             reserved: 0,
-            ctime: 0,
-            cdate: 0,
-            adate: 0,
+            ctime: now.fat_time(),
+            cdate: now.fat_date(),
+            adate: now.fat_date(),
             first_cluster_high: (root_cluser >> 16) as u16,
-            mtime: 0,
-            mdate: 0,
+            mtime: now.fat_time(),
+            mdate: now.fat_date(),
             ctime_tenth: 0,
             first_cluster_low: (root_cluser & 0xFFFF) as u16,
             size: 0,
@@ -277,6 +303,7 @@ impl FAT32 {
         name: &str,
     ) -> Option<DirectoryEntry> {
         let new_cluster = self.find_free_cluster(ata)?;
+        let now = time::now_datetime();
 
         self.write_fat_entry(ata, new_cluster, 0x0FFFFFFF); // Marquer la fin de la chaîne
 
@@ -287,12 +314,12 @@ impl FAT32 {
             name: [b' '; 11],
             attr: 0x10,
             reserved: 0,
-            ctime: 0,
-            cdate: 0,
-            adate: 0,
+            ctime: now.fat_time(),
+            cdate: now.fat_date(),
+            adate: now.fat_date(),
             first_cluster_high: (new_cluster >> 16) as u16,
-            mtime: 0,
-            mdate: 0,
+            mtime: now.fat_time(),
+            mdate: now.fat_date(),
             ctime_tenth: 0,
             first_cluster_low: (new_cluster & 0xFFFF) as u16,
             size: 0,
@@ -324,12 +351,12 @@ impl FAT32 {
             name: [b' '; 11],
             attr: 0x10,
             reserved: 0,
-            ctime: 0,
-            cdate: 0,
-            adate: 0,
+            ctime: now.fat_time(),
+            cdate: now.fat_date(),
+            adate: now.fat_date(),
             first_cluster_high: (new_cluster >> 16) as u16,
-            mtime: 0,
-            mdate: 0,
+            mtime: now.fat_time(),
+            mdate: now.fat_date(),
             ctime_tenth: 0,
             first_cluster_low: (new_cluster & 0xFFFF) as u16,
             size: 0,
@@ -482,7 +509,11 @@ impl FAT32 {
             }
         }
 
+        let now = time::now_datetime();
         entry.size = data.len() as u32;
+        entry.mtime = now.fat_time();
+        entry.mdate = now.fat_date();
+        entry.adate = now.fat_date();
         self.update_directory_entry(ata, dir_cluster, entry_index, &entry);
 
         true
@@ -533,6 +564,223 @@ impl FAT32 {
         Some(data)
     }
 
+    // `data` is padded with zeros to the cluster size
+    pub fn write_cluster(&self, ata: &mut AtaPio, cluster: u32, data: &[u8]) {
+        let first = self.cluster_to_sector(cluster);
+        for i in 0..self.boot_sector.sectors_per_cluster as usize {
+            let mut buffer = [0u8; 512];
+            let start = (i * 512).min(data.len());
+            let end = (start + 512).min(data.len());
+            buffer[..end - start].copy_from_slice(&data[start..end]);
+            ata.write_sector8(first + i as u32, &buffer);
+        }
+    }
+
+    // entry of the file in the first sector of the directory, with its index
+    // there for update_directory_entry
+    fn find_entry(&self, ata: &mut AtaPio, dir_cluster: u32, filename: &str) -> Option<(usize, DirectoryEntry)> {
+        let mut buffer = [0u8; 512];
+        ata.read_sector8(self.cluster_to_sector(dir_cluster), &mut buffer);
+
+        (0..512 / size_of::<DirectoryEntry>()).find_map(|index| {
+            let entry: DirectoryEntry =
+                unsafe { core::ptr::read(buffer.as_ptr().add(index * 32) as *const _) };
+            (entry.is_valid() && name_matches(&entry, filename)).then_some((index, entry))
+        })
+    }
+
+    // a zeroed cluster ending its chain
+    fn allocate_cluster(&mut self, ata: &mut AtaPio) -> Option<u32> {
+        let cluster = self.find_free_cluster(ata)?;
+        self.write_fat_entry(ata, cluster, FAT_END);
+        self.write_cluster(ata, cluster, &[]);
+        Some(cluster)
+    }
+
+    // cluster after `cluster` in its chain, with `allocate` the chain grows
+    // when it ends there
+    fn next_cluster(&mut self, ata: &mut AtaPio, cluster: u32, allocate: bool) -> Option<u32> {
+        let next = self.read_fat_entry(ata, cluster);
+        if !is_chain_end(next) {
+            return Some(next);
+        }
+        if !allocate {
+            return None;
+        }
+
+        let next = self.allocate_cluster(ata)?;
+        self.write_fat_entry(ata, cluster, next);
+        Some(next)
+    }
+
+    // cluster `index` of the file, see next_cluster for `allocate`
+    fn file_cluster(&mut self, ata: &mut AtaPio, entry: &mut DirectoryEntry, index: u64, allocate: bool) -> Option<u32> {
+        let mut cluster = entry.cluster();
+        if cluster == 0 {
+            if !allocate {
+                return None;
+            }
+            cluster = self.allocate_cluster(ata)?;
+            entry.first_cluster_low = (cluster & 0xFFFF) as u16;
+            entry.first_cluster_high = ((cluster >> 16) & 0xFFFF) as u16;
+        }
+
+        for _ in 0..index {
+            cluster = self.next_cluster(ata, cluster, allocate)?;
+        }
+        Some(cluster)
+    }
+
+    fn free_chain(&mut self, ata: &mut AtaPio, mut cluster: u32) {
+        while !is_chain_end(cluster) {
+            let next = self.read_fat_entry(ata, cluster);
+            self.write_fat_entry(ata, cluster, 0);
+            cluster = next;
+        }
+    }
+
+    // zeros the last cluster of the file past `size`, so a file growing over
+    // it reads zeros there
+    fn zero_tail(&mut self, ata: &mut AtaPio, entry: &mut DirectoryEntry, size: u64) {
+        let cluster_size = self.cluster_size as u64;
+        if size.is_multiple_of(cluster_size) {
+            return;
+        }
+
+        if let Some(cluster) = self.file_cluster(ata, entry, size / cluster_size, false) {
+            let mut data = self.read_cluster(ata, cluster);
+            data[(size % cluster_size) as usize..].fill(0);
+            self.write_cluster(ata, cluster, &data);
+        }
+    }
+
+    // bytes of the file at `offset`, fewer at the end of the file. only the
+    // clusters under the range are read
+    pub fn read_at(
+        &mut self,
+        ata: &mut AtaPio,
+        dir_cluster: u32,
+        filename: &str,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> Option<usize> {
+        let (_, mut entry) = self.find_entry(ata, dir_cluster, filename)?;
+        let size = entry.size as u64;
+        if offset >= size || buf.is_empty() {
+            return Some(0);
+        }
+
+        let cluster_size = self.cluster_size as u64;
+        let len = buf.len().min((size - offset) as usize);
+        let mut cluster = self.file_cluster(ata, &mut entry, offset / cluster_size, false)?;
+        let mut done = 0;
+        loop {
+            let pos = offset + done as u64;
+            let start = (pos % cluster_size) as usize;
+            let chunk = (cluster_size as usize - start).min(len - done);
+            buf[done..done + chunk].copy_from_slice(&self.read_cluster(ata, cluster)[start..start + chunk]);
+            done += chunk;
+
+            if done == len {
+                return Some(len);
+            }
+            cluster = self.next_cluster(ata, cluster, false)?;
+        }
+    }
+
+    // only the clusters under the range are read and written, the chain grows
+    // as needed. None when the file is missing or the disk is full
+    pub fn write_at(
+        &mut self,
+        ata: &mut AtaPio,
+        dir_cluster: u32,
+        filename: &str,
+        buf: &[u8],
+        offset: u64,
+    ) -> Option<usize> {
+        let (index, mut entry) = self.find_entry(ata, dir_cluster, filename)?;
+        // the size is 32 bits
+        let end = offset.checked_add(buf.len() as u64).filter(|end| *end <= u32::MAX as u64)?;
+        if buf.is_empty() {
+            return Some(0);
+        }
+
+        let size = entry.size as u64;
+        if offset > size {
+            self.zero_tail(ata, &mut entry, size);
+        }
+
+        let cluster_size = self.cluster_size as u64;
+        let mut cluster = self.file_cluster(ata, &mut entry, offset / cluster_size, true)?;
+        let mut done = 0;
+        loop {
+            let pos = offset + done as u64;
+            let start = (pos % cluster_size) as usize;
+            let chunk = (cluster_size as usize - start).min(buf.len() - done);
+
+            // a whole cluster doesn't need its old content
+            let mut data = if chunk == cluster_size as usize {
+                vec![0; chunk]
+            } else {
+                self.read_cluster(ata, cluster)
+            };
+            data[start..start + chunk].copy_from_slice(&buf[done..done + chunk]);
+            self.write_cluster(ata, cluster, &data);
+            done += chunk;
+
+            if done == buf.len() {
+                break;
+            }
+            cluster = self.next_cluster(ata, cluster, true)?;
+        }
+
+        let now = time::now_datetime();
+        entry.size = size.max(end) as u32;
+        entry.mtime = now.fat_time();
+        entry.mdate = now.fat_date();
+        entry.adate = now.fat_date();
+        self.update_directory_entry(ata, dir_cluster, index, &entry);
+
+        Some(done)
+    }
+
+    // cuts or extends the file, the clusters past the new end go back to the
+    // fat and the new bytes read as zeros
+    pub fn set_len(&mut self, ata: &mut AtaPio, dir_cluster: u32, filename: &str, len: u64) -> Option<()> {
+        let (index, mut entry) = self.find_entry(ata, dir_cluster, filename)?;
+        if len > u32::MAX as u64 {
+            return None;
+        }
+
+        let cluster_size = self.cluster_size as u64;
+        let size = entry.size as u64;
+        let clusters = len.div_ceil(cluster_size);
+        if len < size {
+            if clusters == 0 {
+                self.free_chain(ata, entry.cluster());
+                entry.first_cluster_low = 0;
+                entry.first_cluster_high = 0;
+            } else if let Some(last) = self.file_cluster(ata, &mut entry, clusters - 1, false) {
+                let rest = self.read_fat_entry(ata, last);
+                self.write_fat_entry(ata, last, FAT_END);
+                self.free_chain(ata, rest);
+                self.zero_tail(ata, &mut entry, len);
+            }
+        } else if len > size {
+            self.zero_tail(ata, &mut entry, size);
+            // the chain always covers the size
+            self.file_cluster(ata, &mut entry, clusters - 1, true)?;
+        }
+
+        let now = time::now_datetime();
+        entry.size = len as u32;
+        entry.mtime = now.fat_time();
+        entry.mdate = now.fat_date();
+        self.update_directory_entry(ata, dir_cluster, index, &entry);
+
+        Some(())
+    }
+
     pub fn update_directory_entry(
         &mut self,
         ata: &mut AtaPio,
@@ -0,0 +1,144 @@
+// file pages shared by every mapping of a file
+//
+// a page is loaded from the filesystem the first time it is mapped and stays
+// here, so the next process mapping it gets the same frame
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+
+use super::{with_root, Path};
+use crate::allocator::paging::{physical_memory_offset, KernelFrameAllocator};
+
+// there is only the root filesystem for now
+pub const ROOT_FS_ID: usize = 0;
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageKey {
+    pub fs: usize,
+    pub inode: u64,
+    // page aligned file offset
+    pub offset: u64,
+}
+
+struct CachedPage {
+    frame: PhysFrame,
+    // number of page table entries using the frame
+    refs: usize,
+}
+
+pub struct PageCache {
+    pages: BTreeMap<PageKey, CachedPage>,
+}
+
+pub static PAGE_CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+
+fn frame_bytes(frame: PhysFrame) -> &'static mut [u8] {
+    let virt = physical_memory_offset() + frame.start_address().as_u64();
+    unsafe { core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), PAGE_SIZE as usize) }
+}
+
+impl PageCache {
+    pub const fn new() -> Self {
+        PageCache { pages: BTreeMap::new() }
+    }
+
+    // frame holding the page, with one more reference
+    pub fn get(&mut self, key: PageKey, path: &str) -> Option<PhysFrame> {
+        if let Some(page) = self.pages.get_mut(&key) {
+            page.refs += 1;
+            return Some(page.frame);
+        }
+
+        // only the page is read, past the end of the file it is zeros
+        let frame = KernelFrameAllocator::shared().allocate_frame()?;
+        let bytes = frame_bytes(frame);
+        let len = match with_root(|fs| fs.read_at(Path::new(path), bytes, key.offset)) {
+            Ok(len) => len,
+            Err(_) => {
+                unsafe { KernelFrameAllocator::shared().deallocate_frame(frame) };
+                return None;
+            }
+        };
+        bytes[len..].fill(0);
+
+        self.pages.insert(key, CachedPage { frame, refs: 1 });
+        Some(frame)
+    }
+
+    pub fn release(&mut self, key: PageKey) {
+        if let Some(page) = self.pages.get_mut(&key) {
+            page.refs = page.refs.saturating_sub(1);
+        }
+    }
+
+    pub fn contains_frame(&self, frame: PhysFrame) -> bool {
+        self.pages.values().any(|page| page.frame == frame)
+    }
+
+    pub fn write_back(&self, key: PageKey, path: &str, file_size: u64) -> Option<()> {
+        let page = self.pages.get(&key)?;
+        let len = file_size.saturating_sub(key.offset).min(PAGE_SIZE) as usize;
+        if len == 0 {
+            return Some(());
+        }
+
+        let bytes = frame_bytes(page.frame);
+        with_root(|fs| fs.write_at(Path::new(path), &bytes[..len], key.offset))
+            .ok()
+            .map(|_| ())
+    }
+
+    // copies what write() put at `offset` of a file into its cached pages,
+    // so the mappings see it like they would a store through another mapping
+    pub fn update(&mut self, fs: usize, inode: u64, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let first = PageKey { fs, inode, offset: offset - offset % PAGE_SIZE };
+        let last = PageKey { fs, inode, offset: end };
+
+        for (key, page) in self.pages.range(first..last) {
+            let start = offset.max(key.offset);
+            let stop = end.min(key.offset + PAGE_SIZE);
+            frame_bytes(page.frame)[(start - key.offset) as usize..(stop - key.offset) as usize]
+                .copy_from_slice(&data[(start - offset) as usize..(stop - offset) as usize]);
+        }
+    }
+
+    // frees the pages nobody maps, returns how many were freed
+    pub fn evict_unused(&mut self) -> usize {
+        let mut allocator = KernelFrameAllocator::shared();
+        let before = self.pages.len();
+
+        self.pages.retain(|_, page| {
+            if page.refs == 0 {
+                unsafe { allocator.deallocate_frame(page.frame) };
+                false
+            } else {
+                true
+            }
+        });
+
+        before - self.pages.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+// path and inode of a mapped file, what a file backed area needs to reach its
+// pages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRef {
+    pub path: String,
+    pub inode: u64,
+}
+
+impl FileRef {
+    pub fn key(&self, offset: u64) -> PageKey {
+        PageKey { fs: ROOT_FS_ID, inode: self.inode, offset }
+    }
+}
@@ -0,0 +1,21 @@
+// files made up by the kernel when they are read, under /proc
+
+use alloc::vec::Vec;
+
+use crate::allocator;
+
+pub const FILES: &[&str] = &["/proc/heap"];
+
+pub fn exists(path: &str) -> bool {
+    FILES.contains(&path)
+}
+
+// current content of a /proc file
+pub fn read(path: &str) -> Option<Vec<u8>> {
+    let content = match path {
+        "/proc/heap" => allocator::stats::report(),
+        _ => return None,
+    };
+
+    Some(content.into_bytes())
+}
@@ -134,8 +134,11 @@ lazy_static! {
         static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
         let stack_start = VirtAddr::from_ptr(&raw const STACK);
         let stack_end = stack_start + STACK_SIZE as u64;
+        // its own stack, the double fault of a kernel stack overflow can't
+        // run on the overflowed one
+        static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            stack_end
+            VirtAddr::from_ptr(&raw const DOUBLE_FAULT_STACK) + STACK_SIZE as u64
         };
 
 
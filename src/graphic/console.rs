@@ -8,13 +8,15 @@ use super::{
 use crate::graphic::framebuffer::FrameBuffer;
 use crate::graphic::text::{TextBuffer, TextEdit};
 use crate::graphic::windows::WindowManager;
-use crate::{drivers::keyboard::Key, error};
+use crate::{allocator, drivers::keyboard::Key, error, thread};
 use core::fmt::Write;
 
 pub enum ConsoleCommand {
     Clear,
     Print(Expr),
     Println(Expr),
+    Strace(usize),
+    Heap,
 }
 
 impl ConsoleCommand {
@@ -36,6 +38,11 @@ impl ConsoleCommand {
                     None
                 }
             }
+            Some("strace") => {
+                let pid = parts.next()?.parse::<usize>().ok()?;
+                Some(ConsoleCommand::Strace(pid))
+            }
+            Some("heap") => Some(ConsoleCommand::Heap),
             _ => None,
         }
     }
@@ -96,6 +103,8 @@ impl Console {
                 ConsoleCommand::Clear => self.clear(),
                 ConsoleCommand::Print(expr) => self.print(expr),
                 ConsoleCommand::Println(expr) => self.println(expr),
+                ConsoleCommand::Strace(pid) => self.strace(pid),
+                ConsoleCommand::Heap => self.heap(),
             }
         } else {
             error!("Unknown command: {}", cmd);
@@ -128,6 +137,21 @@ impl Console {
         self.print(expr);
         self.text_buffer_mut().write('\n');
     }
+
+    pub fn strace(&mut self, pid: usize) {
+        match thread::toggle_trace(pid) {
+            Some(true) => writeln!(self.text_buffer_mut(), "strace: tracing pid {}", pid).unwrap(),
+            Some(false) => writeln!(self.text_buffer_mut(), "strace: stopped tracing pid {}", pid).unwrap(),
+            None => {
+                error!("strace: no process {}", pid);
+            }
+        }
+    }
+
+    pub fn heap(&mut self) {
+        let report = allocator::stats::report();
+        self.text_buffer_mut().write_string(&report);
+    }
 }
 
 impl Application for Console {
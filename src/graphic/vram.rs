@@ -1,7 +1,33 @@
 use crate::io::{inl, outl};
 use crate::io::pci::{pci_read, pci_read_bar};
+use crate::allocator::paging::PagingManager;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size2MiB, Size4KiB, Translate};
 
-pub const VRAM_VIRT_ADDR: u64 = 0xAFF0_0000;
+// start of the kernel's framebuffer mapping, 2 MiB aligned
+pub const VRAM_VIRT_ADDR: u64 = 0xFFFF_9000_0000_0000;
+
+// maps the bootloader's framebuffer at VRAM_VIRT_ADDR, with 2 MiB pages
+// where its physical address allows it, and returns where it starts
+pub fn map_vram(paging_manager: &mut PagingManager, framebuffer: &bootloader_api::info::FrameBuffer) -> VirtAddr {
+    let phys = paging_manager.mapper
+        .translate_addr(VirtAddr::from_ptr(framebuffer.buffer().as_ptr()))
+        .expect("framebuffer isn't mapped");
+
+    // same offset in a 2 MiB page on both sides, so the big pages line up
+    let virt = VirtAddr::new(VRAM_VIRT_ADDR + phys.as_u64() % Size2MiB::SIZE);
+    let frame_start = phys.align_down(Size4KiB::SIZE);
+    let size = framebuffer.info().byte_len + (phys - frame_start) as usize;
+
+    paging_manager.map_memory(
+        virt.align_down(Size4KiB::SIZE),
+        size,
+        frame_start,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::WRITE_THROUGH,
+    );
+
+    virt
+}
 
 pub fn find_gpu() -> Option<(u8, u8, u8)> {
     for bus in 0..=255 {
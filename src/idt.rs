@@ -4,16 +4,21 @@ use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin::Mutex;
 use x86_64::{
+    PrivilegeLevel, VirtAddr,
     instructions::port::Port,
+    registers::control::Cr2,
     structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
 
+use crate::gdt;
+use crate::syscall;
 use crate::thread;
 
 use crate::{
     context::GLOBAL_CONTEXT,
+    drivers::char_device::uart,
     drivers::keyboard::{KEYBOARD, Keyboard},
-    info,
+    error, info,
     io::serial::SerialPortWriter,
     print, println, println_serial, print_serial
 };
@@ -36,11 +41,24 @@ lazy_static! {
         idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
         idt.device_not_available
             .set_handler_fn(device_not_available_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt[32].set_handler_fn(timer_handler);
         idt[33].set_handler_fn(keyboard_handler);
-        idt[0x80].set_handler_fn(sys_call_handler);
+        idt[35].set_handler_fn(com2_handler);
+        idt[36].set_handler_fn(com1_handler);
+        // raw stub, the handler needs the user registers and has to be
+        // reachable from ring 3. it takes the x86_64 convention, not the
+        // i386 one, see asm/syscall.asm
+        unsafe {
+            idt[0x80]
+                .set_handler_addr(VirtAddr::new(syscall::int80_handler as *const () as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         idt
     };
 }
@@ -86,6 +104,11 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    // a kernel stack overflow faults again while pushing the page fault
+    if let Some(tid) = Cr2::read().ok().and_then(thread::stack_overflow_at) {
+        panic!("EXCEPTION: Double fault, stack overflow in thread {}\n{:#?}", tid, stack_frame);
+    }
+
     panic!("EXCEPTION: Double fault\n{:#?}", stack_frame);
 }
 
@@ -93,6 +116,20 @@ extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    if let Ok(addr) = Cr2::read() {
+        if thread::handle_page_fault(addr, error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)) {
+            return;
+        }
+
+        if let Some(tid) = thread::stack_overflow_at(addr) {
+            if error_code.contains(PageFaultErrorCode::USER_MODE) {
+                error!("stack overflow in thread {}", tid);
+                thread::exit_current(thread::EXIT_FAULT);
+            }
+            panic!("stack overflow in thread {}\n{:#?}", tid, stack_frame);
+        }
+    }
+
     panic!(
         "EXCEPTION: Page fault\n{:#?} error_code: {:?}",
         stack_frame, error_code
@@ -113,22 +150,20 @@ extern "x86-interrupt" fn keyboard_handler(_stack_frame: InterruptStackFrame) {
 }
 
 extern "x86-interrupt" fn timer_handler(_stack_frame: InterruptStackFrame) {
+    crate::time::tick();
     unsafe { PICS.lock().notify_end_of_interrupt(32) };
 }
 
-extern "x86-interrupt" fn sys_call_handler(stack_frame: InterruptStackFrame) {
-    // let mut stack = Stack::load();
-    // let mut regs = Registers::save();
-    // let mut args = Args::new(stack, regs);
-    // let syscall_number = regs.rax;
-    // match syscall_number {
-    //     0 => {
-    //         SyscallWrite.handle(stack_frame, args);
-    //     }
-    //     _ => {
-    //         panic!("Unknown syscall number: {}", syscall_number);
-    //     }
-    // };
+// IRQ4, shared by COM1 and COM3
+extern "x86-interrupt" fn com1_handler(_stack_frame: InterruptStackFrame) {
+    uart::handle_interrupt();
+    unsafe { PICS.lock().notify_end_of_interrupt(36) };
+}
+
+// IRQ3, shared by COM2 and COM4
+extern "x86-interrupt" fn com2_handler(_stack_frame: InterruptStackFrame) {
+    uart::handle_interrupt();
+    unsafe { PICS.lock().notify_end_of_interrupt(35) };
 }
 
 pub fn init_pic() {
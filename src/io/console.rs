@@ -0,0 +1,66 @@
+// the kernel console, what is written to it shows in its window and what is
+// typed on the keyboard is read from it
+
+use alloc::string::String;
+
+use super::irq_mutex::IrqMutex;
+use super::port::{Device, DeviceError, Readiness};
+use super::ring_buffer::RingBuffer;
+use crate::graphic::text::TextBuffer;
+use crate::thread;
+
+// typed bytes kept until read, more are dropped
+const INPUT_SIZE: usize = 1024;
+
+static INPUT: IrqMutex<RingBuffer<INPUT_SIZE>> = IrqMutex::new(RingBuffer::new());
+
+// called from the keyboard and serial interrupts
+pub fn push_input(bytes: &[u8]) {
+    let mut input = INPUT.lock();
+    for &byte in bytes {
+        if !input.push(byte) {
+            break;
+        }
+    }
+}
+
+pub struct ConsoleDevice {
+    // the keyboard interrupt echoes keys through it
+    text: IrqMutex<TextBuffer>,
+}
+
+impl ConsoleDevice {
+    pub fn new(text: TextBuffer) -> Self {
+        ConsoleDevice { text: IrqMutex::new(text) }
+    }
+}
+
+impl Device for ConsoleDevice {
+    // what was typed so far, waits for a key when nothing was
+    fn read(&self, buf: &mut [u8], _offset: u64) -> Result<usize, DeviceError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let len = INPUT.lock().read(buf);
+            if len > 0 {
+                return Ok(len);
+            }
+            thread::wait_for_interrupt();
+        }
+    }
+
+    fn write(&self, buf: &[u8], _offset: u64) -> Result<usize, DeviceError> {
+        self.text.lock().write_string(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> Readiness {
+        if !INPUT.lock().is_empty() {
+            Readiness::READABLE | Readiness::WRITABLE
+        } else {
+            Readiness::WRITABLE
+        }
+    }
+}
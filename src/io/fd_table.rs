@@ -0,0 +1,117 @@
+// file descriptors of a process
+//
+// a descriptor is a small integer naming an open file description. dup and
+// fork make several descriptors share one description, with its offset, the
+// description is closed when the last of them is
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::pipe::PipeEnd;
+use super::port::{Device, Fd, Readiness};
+use crate::fs::File;
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+// descriptors are below this
+pub const MAX_FDS: usize = 256;
+
+// the file keeps the offset
+pub struct OpenFile {
+    pub file: File,
+    pub path: String,
+}
+
+pub enum FileDescription {
+    // port of a kernel device, like the console
+    Port(usize),
+    File(OpenFile),
+    Pipe(PipeEnd),
+}
+
+impl FileDescription {
+    // files are always ready
+    pub fn readiness(&self) -> Readiness {
+        match self {
+            FileDescription::Port(port) => Fd(*port).poll(),
+            FileDescription::File(_) => Readiness::READABLE | Readiness::WRITABLE,
+            FileDescription::Pipe(end) => end.poll(),
+        }
+    }
+}
+
+pub type FileHandle = Arc<Mutex<FileDescription>>;
+
+pub fn new_handle(description: FileDescription) -> FileHandle {
+    Arc::new(Mutex::new(description))
+}
+
+// cloned on fork, the clone shares the descriptions
+#[derive(Clone, Default)]
+pub struct FdTable {
+    fds: BTreeMap<usize, FileHandle>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        FdTable { fds: BTreeMap::new() }
+    }
+
+    // stdin, stdout and stderr all on the port `port`
+    pub fn with_stdio(port: usize) -> Self {
+        let mut table = FdTable::new();
+        let console = new_handle(FileDescription::Port(port));
+        for fd in [STDIN, STDOUT, STDERR] {
+            table.fds.insert(fd, console.clone());
+        }
+        table
+    }
+
+    pub fn get(&self, fd: usize) -> Option<FileHandle> {
+        self.fds.get(&fd).cloned()
+    }
+
+    fn lowest_free(&self) -> Option<usize> {
+        (0..MAX_FDS).find(|fd| !self.fds.contains_key(fd))
+    }
+
+    // lowest free descriptor, None when the table is full
+    pub fn insert(&mut self, handle: FileHandle) -> Option<usize> {
+        let fd = self.lowest_free()?;
+        self.fds.insert(fd, handle);
+        Some(fd)
+    }
+
+    pub fn close(&mut self, fd: usize) -> Option<FileHandle> {
+        self.fds.remove(&fd)
+    }
+
+    pub fn dup(&mut self, fd: usize) -> Option<usize> {
+        let handle = self.get(fd)?;
+        self.insert(handle)
+    }
+
+    // `new` is closed first if it was open, None when `old` isn't
+    pub fn dup2(&mut self, old: usize, new: usize) -> Option<usize> {
+        let handle = self.get(old)?;
+        if new >= MAX_FDS {
+            return None;
+        }
+        if old != new {
+            self.fds.insert(new, handle);
+        }
+        Some(new)
+    }
+
+    pub fn close_all(&mut self) {
+        self.fds.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+}
@@ -0,0 +1,72 @@
+// spin lock that also masks interrupts while it is held
+//
+// data shared with an interrupt handler needs it: if the handler fired while
+// the interrupted code held a plain spin lock it would spin forever on it
+
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+pub struct IrqMutexGuard<'a, T> {
+    // taken out before interrupts come back
+    guard: Option<MutexGuard<'a, T>>,
+    enable: bool,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        IrqMutex { inner: Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let enable = interrupts::are_enabled();
+        interrupts::disable();
+
+        IrqMutexGuard {
+            guard: Some(self.inner.lock()),
+            enable,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let enable = interrupts::are_enabled();
+        interrupts::disable();
+
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqMutexGuard { guard: Some(guard), enable }),
+            None => {
+                if enable {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.guard.take();
+        if self.enable {
+            interrupts::enable();
+        }
+    }
+}
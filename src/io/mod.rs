@@ -1,7 +1,12 @@
 use x86_64::instructions::port::Port;
 
+pub mod console;
+pub mod fd_table;
+pub mod irq_mutex;
 pub mod pci;
+pub mod pipe;
 pub mod port;
+pub mod ring_buffer;
 pub mod serial;
 
 pub fn outb(port: u16, value: u8) {
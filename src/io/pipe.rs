@@ -0,0 +1,135 @@
+// anonymous pipes, a bounded ring buffer between a read end and a write end
+//
+// a reader waits while the buffer is empty and a writer while it is full.
+// each end is counted, readers get the end of file once every write end is
+// closed and writers get BrokenPipe once every read end is
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::port::{Device, DeviceError, Readiness};
+use crate::thread;
+
+// bytes a pipe holds before writers wait
+pub const PIPE_SIZE: usize = 4096;
+
+struct PipeState {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+struct Pipe {
+    state: Mutex<PipeState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeEndKind {
+    Read,
+    Write,
+}
+
+// one end of a pipe, dropping it closes it
+pub struct PipeEnd {
+    pipe: Arc<Pipe>,
+    kind: PipeEndKind,
+}
+
+// (read end, write end)
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buf: VecDeque::with_capacity(PIPE_SIZE),
+            readers: 1,
+            writers: 1,
+        }),
+    });
+
+    (
+        PipeEnd { pipe: pipe.clone(), kind: PipeEndKind::Read },
+        PipeEnd { pipe, kind: PipeEndKind::Write },
+    )
+}
+
+impl PipeEnd {
+    pub fn kind(&self) -> PipeEndKind {
+        self.kind
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        match self.kind {
+            PipeEndKind::Read => state.readers -= 1,
+            PipeEndKind::Write => state.writers -= 1,
+        }
+    }
+}
+
+impl Device for PipeEnd {
+    // what is buffered, waits when there is nothing and a writer is left
+    fn read(&self, buf: &mut [u8], _offset: u64) -> Result<usize, DeviceError> {
+        if self.kind != PipeEndKind::Read {
+            return Err(DeviceError::NotSupported);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            {
+                let mut state = self.pipe.state.lock();
+                if !state.buf.is_empty() {
+                    let len = buf.len().min(state.buf.len());
+                    for (dst, src) in buf.iter_mut().zip(state.buf.drain(..len)) {
+                        *dst = src;
+                    }
+                    return Ok(len);
+                }
+                if state.writers == 0 {
+                    return Ok(0);
+                }
+            }
+            thread::wait_for_interrupt();
+        }
+    }
+
+    // writes everything, waiting for room as needed, unless the last reader
+    // goes away meanwhile
+    fn write(&self, buf: &[u8], _offset: u64) -> Result<usize, DeviceError> {
+        if self.kind != PipeEndKind::Write {
+            return Err(DeviceError::NotSupported);
+        }
+
+        let mut written = 0;
+        loop {
+            {
+                let mut state = self.pipe.state.lock();
+                if state.readers == 0 {
+                    return if written > 0 { Ok(written) } else { Err(DeviceError::BrokenPipe) };
+                }
+
+                let len = (PIPE_SIZE - state.buf.len()).min(buf.len() - written);
+                state.buf.extend(&buf[written..written + len]);
+                written += len;
+                if written == buf.len() {
+                    return Ok(written);
+                }
+            }
+            thread::wait_for_interrupt();
+        }
+    }
+
+    fn poll(&self) -> Readiness {
+        let state = self.pipe.state.lock();
+        match self.kind {
+            PipeEndKind::Read if state.writers == 0 => Readiness::READABLE | Readiness::HANGUP,
+            PipeEndKind::Read if !state.buf.is_empty() => Readiness::READABLE,
+            PipeEndKind::Write if state.readers == 0 => Readiness::WRITABLE | Readiness::HANGUP,
+            PipeEndKind::Write if state.buf.len() < PIPE_SIZE => Readiness::WRITABLE,
+            _ => Readiness::empty(),
+        }
+    }
+}
@@ -1,162 +1,137 @@
-use alloc::boxed::Box;
-use alloc::rc::Rc;
-use alloc::vec::Vec;
-use core::cell::RefCell;
+// kernel devices and the ports naming them
+//
+// a device is shared through an Arc and does its own locking. the port table
+// is only locked to find a device, never while the device works, so a
+// device may be used from an interrupt handler while a syscall uses it too
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use bitflags::bitflags;
 use core::sync::atomic::Ordering;
 use core::{fmt::Write, sync::atomic::AtomicUsize};
-use lazy_static::lazy_static;
-use spin::Mutex;
-use crate::warn;
 
-pub static FD_COUNTER: AtomicUsize = AtomicUsize::new(0);
-
-pub static PORTS: Mutex<Ports> = Mutex::new(Ports::new());
-
-pub static STDIO: AtomicFd = AtomicFd::new();
-
-pub fn new_port<R, W>(read: R, write: W) -> Fd
-where
-    R: FnMut() -> Vec<u8> + 'static,
-    W: FnMut(&[u8]) + 'static,
-{
-    let fd = FD_COUNTER.fetch_add(1, Ordering::Relaxed);
-    PORTS.lock().add_port(Port::new(read, write, fd));
-    Fd(fd)
-}
-
-pub struct Ports {
-    ports: Vec<Port>,
+use super::irq_mutex::IrqMutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    // no device behind the port
+    NoDevice,
+    NotSupported,
+    InvalidArgument,
+    Io,
+    // the other end of a pipe is closed
+    BrokenPipe,
 }
 
-impl Ports {
-    pub const fn new() -> Self {
-        Ports { ports: Vec::new() }
+bitflags! {
+    // what a read or a write would do right now without waiting
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Readiness: u32 {
+        // data is there, or a read returns the end of file
+        const READABLE = 1;
+        const WRITABLE = 1 << 1;
+        // the other side is gone
+        const HANGUP = 1 << 2;
     }
+}
 
-    pub fn new_port<R, W>(read: R, write: W) -> Fd
-    where
-        R: FnMut() -> Vec<u8> + 'static,
-        W: FnMut(&[u8]) + 'static,
-    {
-        let fd = FD_COUNTER.fetch_add(1, Ordering::Relaxed);
-        PORTS.lock().add_port(Port::new(read, write, fd));
-        Fd(fd)
-    }
+pub trait Device: Send + Sync {
+    // bytes read at `offset`, devices without a position ignore it. 0 means
+    // nothing to read
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, DeviceError>;
+    fn write(&self, buf: &[u8], offset: u64) -> Result<usize, DeviceError>;
 
-    pub fn add_port(&mut self, port: Port) {
-        self.ports.push(port);
+    fn ioctl(&self, _request: u64, _arg: u64) -> Result<u64, DeviceError> {
+        Err(DeviceError::NotSupported)
     }
 
-    pub fn get_port(&self, fd: usize) -> Option<&Port> {
-        self.ports.iter().find(|port| port.fd() == fd)
+    // bytes of a device with an end, like a file
+    fn size(&self) -> Result<u64, DeviceError> {
+        Err(DeviceError::NotSupported)
     }
 
-    pub fn remove_port(&mut self, fd: usize) -> Option<Port> {
-        self.ports
-            .iter()
-            .position(|port| port.fd() == fd)
-            .map(|pos| self.ports.remove(pos))
+    fn set_len(&self, _len: u64) -> Result<(), DeviceError> {
+        Err(DeviceError::NotSupported)
     }
 
-    pub fn len(&self) -> usize {
-        self.ports.len()
+    // devices that never wait are always ready
+    fn poll(&self) -> Readiness {
+        Readiness::READABLE | Readiness::WRITABLE
     }
 }
 
-#[derive(Clone)]
-pub struct Port {
-    read: Rc<RefCell<dyn FnMut() -> Vec<u8>>>,
-    write: Rc<RefCell<dyn FnMut(&[u8])>>,
-    fd: usize,
+static NEXT_PORT: AtomicUsize = AtomicUsize::new(0);
+
+// the keyboard interrupt writes to the console, so interrupts are masked
+// while the table is locked
+static PORTS: IrqMutex<BTreeMap<usize, Arc<dyn Device>>> = IrqMutex::new(BTreeMap::new());
+
+pub static STDIO: AtomicFd = AtomicFd::new();
+
+pub fn register(device: Arc<dyn Device>) -> Fd {
+    let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+    PORTS.lock().insert(port, device);
+    Fd(port)
 }
 
-unsafe impl Send for Port {}
-unsafe impl Sync for Port {}
+// the device lives on while someone still uses it
+pub fn unregister(port: usize) -> Option<Arc<dyn Device>> {
+    PORTS.lock().remove(&port)
+}
 
-impl core::fmt::Debug for Port {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("Port").field("fd", &self.fd).finish()
-    }
+pub fn device(port: usize) -> Option<Arc<dyn Device>> {
+    PORTS.lock().get(&port).cloned()
 }
 
-impl Port {
-    pub fn from_fd(fd: usize) -> Option<Self> {
-        PORTS.lock().get_port(fd).cloned()
-    }
-    fn new<R, W>(read: R, write: W, fd: usize) -> Self
-    where
-        R: FnMut() -> Vec<u8> + 'static,
-        W: FnMut(&[u8]) + 'static,
-    {
-        Port {
-            read: Rc::new(RefCell::new(read)),
-            write: Rc::new(RefCell::new(write)),
-            fd,
-        }
-    }
+// port of a kernel device
+#[derive(Debug)]
+pub struct Fd(pub usize);
 
+impl Fd {
     pub fn fd(&self) -> usize {
-        self.fd
+        self.0
     }
 
-    pub fn write(&mut self, buf: &[u8]) {
-        (self.write.borrow_mut())(buf);
+    pub fn device(&self) -> Option<Arc<dyn Device>> {
+        device(self.0)
     }
 
-    pub fn read(&mut self) -> Vec<u8> {
-        (self.read.borrow_mut())()
+    pub fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, DeviceError> {
+        self.device().ok_or(DeviceError::NoDevice)?.read(buf, offset)
     }
-}
 
-impl Write for Port {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        (self.write.borrow_mut())(s.as_bytes());
-        Ok(())
+    pub fn write(&self, buf: &[u8], offset: u64) -> Result<usize, DeviceError> {
+        self.device().ok_or(DeviceError::NoDevice)?.write(buf, offset)
     }
-}
-
-
-#[derive(Debug)]
-pub struct Fd(pub usize);
 
-impl Fd {
-    pub fn new() -> Self {
-        let fd = FD_COUNTER.fetch_add(1, Ordering::Relaxed);
-        Fd(fd)
+    pub fn ioctl(&self, request: u64, arg: u64) -> Result<u64, DeviceError> {
+        self.device().ok_or(DeviceError::NoDevice)?.ioctl(request, arg)
     }
 
-    pub fn fd(&self) -> usize {
-        self.0
+    pub fn size(&self) -> Result<u64, DeviceError> {
+        self.device().ok_or(DeviceError::NoDevice)?.size()
     }
 
-    pub fn write(&self, buf: &[u8]) {
-        let port = Port::from_fd(self.0);
-        if let Some(mut port) = port {
-            port.write(buf);
-        } else {
-            warn!("no port found");
-        }
+    pub fn set_len(&self, len: u64) -> Result<(), DeviceError> {
+        self.device().ok_or(DeviceError::NoDevice)?.set_len(len)
     }
 
-    pub fn read(&self) -> Option<Vec<u8>> {
-        let port = Port::from_fd(self.0);
-        if let Some(mut port) = port {
-            Some(port.read())
-        } else {
-            None
-        }
+    // a port without a device hangs up
+    pub fn poll(&self) -> Readiness {
+        self.device().map_or(Readiness::HANGUP, |device| device.poll())
     }
 }
 
 impl Write for Fd {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let port = Port::from_fd(self.0);
-        if let Some(mut port) = port {
-            port.write_str(s);
-            Ok(())
-        } else {
-            Err(core::fmt::Error)
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match Fd::write(self, buf, 0) {
+                Ok(0) | Err(_) => return Err(core::fmt::Error),
+                Ok(written) => buf = &buf[written..],
+            }
         }
+        Ok(())
     }
 }
 
@@ -178,13 +153,7 @@ impl AtomicFd {
 
 impl Write for AtomicFd {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let port = Port::from_fd(self.fd());
-        if let Some(mut port) = port {
-            port.write_str(s);
-            Ok(())
-        } else {
-            Err(core::fmt::Error)
-        }
+        Fd(self.fd()).write_str(s)
     }
 }
 
@@ -206,12 +175,6 @@ impl AtomicPort {
 
 impl Write for AtomicPort {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let port = Port::from_fd(self.fd());
-        if let Some(mut port) = port {
-            port.write_str(s);
-            Ok(())
-        } else {
-            Err(core::fmt::Error)
-        }
+        Fd(self.fd()).write_str(s)
     }
 }
@@ -0,0 +1,40 @@
+// fixed size byte queue, it never touches the heap so interrupt handlers can
+// fill it
+
+pub struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        RingBuffer { buf: [0; N], start: 0, len: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // false when it is full, the byte is dropped then
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+
+        self.buf[(self.start + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    // takes the oldest bytes, as many as fit in `buf`
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len);
+        for byte in &mut buf[..len] {
+            *byte = self.buf[self.start];
+            self.start = (self.start + 1) % N;
+        }
+        self.len -= len;
+        len
+    }
+}
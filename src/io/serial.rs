@@ -1,10 +1,8 @@
-use core::{arch::asm, fmt::Write};
+use core::fmt::Write;
 
-use x86_64::instructions::port::Port;
-
-
-const SERIAL_PORT: u16 = 0x3F8; // Adresse du port série (COM1)
+use crate::drivers::char_device::uart;
 
+// COM1, owned by the uart driver
 pub struct SerialPortWriter;
 
 impl SerialPortWriter {
@@ -15,7 +13,7 @@ impl SerialPortWriter {
         }
     }
     pub fn write_byte(&mut self, byte: u8) {
-        serial_write(byte);
+        uart::write_log_byte(byte);
     }
 }
 
@@ -27,45 +25,6 @@ impl Write for SerialPortWriter {
 }
 
 
-fn serial_write(byte: u8) {
-    unsafe {
-        // Attendre que le port soit prêt à recevoir des données
-        while !is_serial_transmit_empty() {}
-
-        // Envoyer le byte dans le port
-        outb(SERIAL_PORT, byte);
-    }
-}
-
-/// Vérifie si le port série est prêt à envoyer des données
-fn is_serial_transmit_empty() -> bool {
-    unsafe { inb(SERIAL_PORT + 5) & 0x20 != 0 }
-}
-
-/// Lecture d'un octet depuis le port série (facultatif si vous en avez besoin)
-fn serial_read() -> u8 {
-    unsafe {
-        while is_serial_data_ready() == false {}
-
-        return inb(SERIAL_PORT);
-    }
-}
-
-/// Vérifie si des données sont prêtes à être lues depuis le port série
-fn is_serial_data_ready() -> bool {
-    unsafe { inb(SERIAL_PORT + 5) & 1 != 0 }
-}
-
-/// Fonctions pour écrire/ lire des données depuis les ports d'IO
-unsafe fn outb(port: u16, value: u8) {
-    Port::new(port).write(value);
-}
-
-unsafe fn inb(port: u16) -> u8 {
-    Port::new(port).read()
-}
-
-
 
 #[macro_export]
 macro_rules! print_serial {
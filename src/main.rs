@@ -6,6 +6,7 @@
 #![allow(static_mut_refs)]
 #![allow(unused)]
 #![feature(ascii_char)]
+#![feature(alloc_error_handler)]
 #![allow(unsafe_op_in_unsafe_fn)]
 #![allow(unused_mut)]
 #![allow(const_item_mutation)]
@@ -27,9 +28,10 @@ mod syscall;
 mod util;
 mod libc;
 mod thread;
+mod time;
 
 use alloc::sync::Arc;
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::{boxed::Box, format, vec, vec::Vec};
 use allocator::memory::init_heap;
 use ata_x86::{ATA_BLOCK_SIZE, list, read};
 use context::{Context, GLOBAL_CONTEXT, init_global_context};
@@ -43,6 +45,7 @@ use core::{
     panic::PanicInfo,
 };
 use drivers::{
+    char_device::uart,
     disk,
     keyboard::{KeyEvent, set_keyboard_handler},
 };
@@ -82,8 +85,12 @@ use x86_64::{
 
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
-    config.mappings.framebuffer = Mapping::FixedAddress(graphic::vram::VRAM_VIRT_ADDR);
+    // mapped again by the kernel at VRAM_VIRT_ADDR with 2 MiB pages
+    config.mappings.framebuffer = Mapping::Dynamic;
     config.mappings.physical_memory = Some(Mapping::Dynamic);
+    // everything the bootloader maps goes to the kernel half, away from the
+    // heap, framebuffer and kernel stacks, user page tables only share it
+    config.mappings.dynamic_range_start = Some(0xFFFF_C000_0000_0000);
     config
 };
 
@@ -109,6 +116,11 @@ pub fn test_keyboard_polling() {
 fn keyboard_handler(key_event: KeyEvent, context: &Mutex<Context>) {
     let mut fd = Fd(STDIO.fd());
 
+    // typed characters are read back from the console
+    if let Some(c) = key_event.key.to_string() {
+        io::console::push_input(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+
     write!(fd,"{}", key_event.key.to_string().unwrap_or('?'));
 }
 
@@ -122,7 +134,8 @@ pub fn check_pic_mask() {
 
 pub unsafe fn unmask_pic() {
     unsafe {
-        Port::new(0x21).write(0xFCu8);
+        // timer, keyboard, COM2/COM4 and COM1/COM3
+        Port::new(0x21).write(0xE4u8);
         Port::new(0xA1).write(0xFFu8); // Unmask IRQ2 (série)
     }
 }
@@ -133,6 +146,7 @@ pub fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     gdt::init_gdt();
     init_idt();
     init_pic();
+    time::init_clock();
     unsafe { unmask_pic() };
 
     x86_64::instructions::interrupts::enable();
@@ -146,9 +160,10 @@ pub fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     );
     syscall::init_syscall();
 
+    let vram_addr = graphic::vram::map_vram(&mut paging_manager, boot_info.framebuffer.as_ref().unwrap());
     let framebuffer = unsafe {
         FrameBuffer::create_from_raw_addr(
-            graphic::vram::VRAM_VIRT_ADDR,
+            vram_addr.as_u64(),
             boot_info.framebuffer.as_ref().unwrap().info(),
         )
     };
@@ -184,15 +199,10 @@ pub fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     );
 
     let mut win_log = WINDOW_MANAGER.lock().new_window(600, 800, 0, 0);
-    let mut text_buffer = TextBuffer::create(1200 - 600, 800, 600, 0);
+    let text_buffer = TextBuffer::create(1200 - 600, 800, 600, 0);
     set_log_output(LogOutput::TextBuffer(win_log));
 
-    let mut stdio = io::port::Ports::new_port(
-        || Vec::new(),
-        move |data| {
-            text_buffer.write_string(core::str::from_utf8(data).unwrap());
-        },
-    );
+    let stdio = io::port::register(Arc::new(io::console::ConsoleDevice::new(text_buffer)));
 
 
     for region in boot_info.memory_regions.into_iter() {
@@ -203,14 +213,36 @@ pub fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     }
 
     io::port::STDIO.set(stdio.fd());
+
+    for com in uart::init(uart::DEFAULT_BAUD) {
+        let port = io::port::register(Arc::new(com));
+        fs::devfs::register(&format!("ttyS{}", com.index()), port.fd());
+        info!("COM{} at {:#x}, {} baud", com.index() + 1, com.base(), com.baud());
+    }
+    // what is typed on -serial stdio reaches stdin like the keyboard does
+    if let Some(com1) = uart::get(0) {
+        com1.set_console(true);
+    }
+
     let mut disk = drivers::disk::ata::AtaPio::detect_disks();
     let mut last = disk.last().unwrap().clone();
-    let mut ext2 = fs::ext2::Ext2FS::from_disk(&mut last).unwrap();
-    use fs::FileSystem;
+    let ext2 = fs::ext2::Ext2FS::from_disk(&mut last).unwrap();
+    fs::mount_root(ext2);
+    // swap only goes where a swap partition or a mkswap header says so
+    for swap in disk.iter_mut() {
+        let disk_sectors = swap.get_info().3 as u64;
+        if let Some((start, sectors)) = thread::swap::find_area(swap, disk_sectors) {
+            thread::swap::enable(Box::new(swap.clone()), start, sectors);
+            if let Some((_, pages)) = thread::swap::usage() {
+                info!("swap enabled, {} pages", pages);
+            }
+            break;
+        }
+    }
     info!("read /hello file");
-    let hello_exe = ext2.read(fs::Path::new("/hello")).unwrap();
+    let hello_exe = fs::with_root(|fs| fs.read(fs::Path::new("/hello"))).unwrap();
     info!("Execute hello");
-    let mut elf = elf::ProgLoader::from_bytes(&hello_exe).unwrap();
+    let mut elf = elf::ProgLoader::from_bytes(&hello_exe).unwrap().with_source("/hello");
     //write!(stdio, "elf: {:#?}", elf);
     elf.execute(&mut paging_manager);
 
@@ -0,0 +1,76 @@
+use crate::fs;
+use crate::io::port::DeviceError;
+
+// the values are the linux ones, so they can be returned as is to programs
+// using the linux personality
+pub const EPERM: i64 = 1;
+pub const ENOENT: i64 = 2;
+pub const ESRCH: i64 = 3;
+pub const EIO: i64 = 5;
+pub const EBADF: i64 = 9;
+pub const ENOMEM: i64 = 12;
+pub const EACCES: i64 = 13;
+pub const EFAULT: i64 = 14;
+pub const EEXIST: i64 = 17;
+pub const ENODEV: i64 = 19;
+pub const ENOTDIR: i64 = 20;
+pub const EISDIR: i64 = 21;
+pub const EINVAL: i64 = 22;
+pub const EMFILE: i64 = 24;
+pub const ENOTTY: i64 = 25;
+pub const ENOSPC: i64 = 28;
+pub const ESPIPE: i64 = 29;
+pub const EPIPE: i64 = 32;
+pub const ERANGE: i64 = 34;
+pub const ENAMETOOLONG: i64 = 36;
+pub const ENOSYS: i64 = 38;
+
+pub fn from_fs_error(err: &fs::Error) -> i64 {
+    match err {
+        fs::Error::FileNotFound => ENOENT,
+        fs::Error::CantUseRelPath => EINVAL,
+        fs::Error::RootDirNotFound => ENOENT,
+        fs::Error::NotADirectory => ENOTDIR,
+        fs::Error::NotAFile => EISDIR,
+        fs::Error::NotEnoughSpace => ENOSPC,
+        fs::Error::AlreadyExists => EEXIST,
+        fs::Error::KernelError(_) => EIO,
+    }
+}
+
+pub fn from_device_error(err: DeviceError) -> i64 {
+    match err {
+        DeviceError::NoDevice => ENODEV,
+        DeviceError::NotSupported => EINVAL,
+        DeviceError::InvalidArgument => EINVAL,
+        DeviceError::Io => EIO,
+        DeviceError::BrokenPipe => EPIPE,
+    }
+}
+
+pub fn name(errno: i64) -> &'static str {
+    match errno {
+        EPERM => "EPERM",
+        ENOENT => "ENOENT",
+        ESRCH => "ESRCH",
+        EIO => "EIO",
+        EBADF => "EBADF",
+        ENOMEM => "ENOMEM",
+        EACCES => "EACCES",
+        EFAULT => "EFAULT",
+        EEXIST => "EEXIST",
+        ENODEV => "ENODEV",
+        ENOTDIR => "ENOTDIR",
+        EISDIR => "EISDIR",
+        EINVAL => "EINVAL",
+        EMFILE => "EMFILE",
+        ENOTTY => "ENOTTY",
+        ENOSPC => "ENOSPC",
+        ESPIPE => "ESPIPE",
+        EPIPE => "EPIPE",
+        ERANGE => "ERANGE",
+        ENAMETOOLONG => "ENAMETOOLONG",
+        ENOSYS => "ENOSYS",
+        _ => "E?",
+    }
+}
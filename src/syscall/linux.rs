@@ -0,0 +1,412 @@
+// linux x86_64 syscall ABI, used by processes with the linux personality so
+// that statically linked musl programs run unchanged
+
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::FsBase;
+
+use super::{errno, file_handle, SyscallCtx, SyscallResult};
+use super::uaccess::{copy_to_user, read_user_bytes, read_user_cstr, write_user};
+use crate::fs::{self, FileKind, Metadata, OpenFlags, Path};
+use crate::io::fd_table::FileDescription;
+use crate::io::pipe::PIPE_SIZE;
+use crate::println_serial;
+use crate::thread::{self, PROCESS_TABLE};
+use crate::time;
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_STAT: u64 = 4;
+pub const SYS_FSTAT: u64 = 5;
+pub const SYS_LSTAT: u64 = 6;
+pub const SYS_POLL: u64 = 7;
+pub const SYS_LSEEK: u64 = 8;
+pub const SYS_MMAP: u64 = 9;
+pub const SYS_MPROTECT: u64 = 10;
+pub const SYS_MUNMAP: u64 = 11;
+pub const SYS_BRK: u64 = 12;
+pub const SYS_RT_SIGACTION: u64 = 13;
+pub const SYS_RT_SIGPROCMASK: u64 = 14;
+pub const SYS_IOCTL: u64 = 16;
+pub const SYS_READV: u64 = 19;
+pub const SYS_WRITEV: u64 = 20;
+pub const SYS_MSYNC: u64 = 26;
+pub const SYS_PIPE: u64 = 22;
+pub const SYS_DUP: u64 = 32;
+pub const SYS_DUP2: u64 = 33;
+pub const SYS_GETPID: u64 = 39;
+pub const SYS_EXIT: u64 = 60;
+pub const SYS_UNAME: u64 = 63;
+pub const SYS_GETUID: u64 = 102;
+pub const SYS_GETGID: u64 = 104;
+pub const SYS_GETEUID: u64 = 107;
+pub const SYS_GETEGID: u64 = 108;
+pub const SYS_ARCH_PRCTL: u64 = 158;
+pub const SYS_GETTID: u64 = 186;
+pub const SYS_GETDENTS64: u64 = 217;
+pub const SYS_SET_TID_ADDRESS: u64 = 218;
+pub const SYS_CLOCK_GETTIME: u64 = 228;
+pub const SYS_EXIT_GROUP: u64 = 231;
+
+const O_ACCMODE: u64 = 0o3;
+const O_WRONLY: u64 = 0o1;
+const O_RDWR: u64 = 0o2;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+const ARCH_SET_FS: u64 = 0x1002;
+const ARCH_GET_FS: u64 = 0x1003;
+
+// bytes of a kernel sigset_t, 64 signals
+const SIGSET_SIZE: u64 = 8;
+
+const CLOCK_REALTIME: u64 = 0;
+const CLOCK_MONOTONIC: u64 = 1;
+
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+const DT_UNKNOWN: u8 = 0;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct LinuxStat {
+    st_dev: u64,
+    st_ino: u64,
+    st_nlink: u64,
+    st_mode: u32,
+    st_uid: u32,
+    st_gid: u32,
+    _pad0: u32,
+    st_rdev: u64,
+    st_size: i64,
+    st_blksize: i64,
+    st_blocks: i64,
+    st_atime: Timespec,
+    st_mtime: Timespec,
+    st_ctime: Timespec,
+    _unused: [i64; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct IoVec {
+    base: u64,
+    len: u64,
+}
+
+#[repr(C)]
+struct UtsName {
+    sysname: [u8; 65],
+    nodename: [u8; 65],
+    release: [u8; 65],
+    version: [u8; 65],
+    machine: [u8; 65],
+    domainname: [u8; 65],
+}
+
+pub fn dispatch(ctx: &SyscallCtx) -> SyscallResult {
+    match ctx.syscall_id {
+        SYS_READ => super::sys_read(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_WRITE => super::sys_write(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_OPEN => sys_open(ctx.rdi, ctx.rsi),
+        SYS_CLOSE => super::sys_close(ctx.rdi),
+        SYS_STAT | SYS_LSTAT => sys_stat(ctx.rdi, ctx.rsi),
+        SYS_FSTAT => sys_fstat(ctx.rdi, ctx.rsi),
+        SYS_POLL => super::sys_poll(ctx.rdi, ctx.rsi, ctx.rdx as i32),
+        SYS_LSEEK => super::sys_lseek(ctx.rdi, ctx.rsi as i64, ctx.rdx),
+        SYS_MMAP => super::sys_mmap(ctx.rdi, ctx.rsi, ctx.rdx, ctx.r10, ctx.r8, ctx.r9),
+        SYS_MPROTECT => super::sys_mprotect(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_MUNMAP => super::sys_munmap(ctx.rdi, ctx.rsi),
+        SYS_MSYNC => super::sys_msync(ctx.rdi, ctx.rsi),
+        SYS_PIPE => super::sys_pipe(ctx.rdi),
+        SYS_DUP => super::sys_dup(ctx.rdi),
+        SYS_DUP2 => super::sys_dup2(ctx.rdi, ctx.rsi),
+        SYS_BRK => sys_brk(ctx.rdi),
+        SYS_RT_SIGACTION => sys_rt_sigaction(ctx.rdx, ctx.r10),
+        SYS_RT_SIGPROCMASK => sys_rt_sigprocmask(ctx.rdx, ctx.r10),
+        SYS_IOCTL => super::sys_ioctl(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_READV => sys_readv(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_WRITEV => sys_writev(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_GETPID | SYS_GETTID | SYS_SET_TID_ADDRESS => Ok(thread::current_pid() as u64),
+        SYS_EXIT | SYS_EXIT_GROUP => thread::exit_current(ctx.rdi as i32),
+        SYS_UNAME => sys_uname(ctx.rdi),
+        SYS_GETUID | SYS_GETGID | SYS_GETEUID | SYS_GETEGID => Ok(0),
+        SYS_ARCH_PRCTL => sys_arch_prctl(ctx.rdi, ctx.rsi),
+        SYS_GETDENTS64 => sys_getdents64(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(ctx.rdi, ctx.rsi),
+        e => {
+            println_serial!("unknown linux syscall {}", e);
+            Err(errno::ENOSYS)
+        }
+    }
+}
+
+fn open_flags(flags: u64) -> OpenFlags {
+    let mut open_flags = match flags & O_ACCMODE {
+        O_WRONLY => OpenFlags::WRITE,
+        O_RDWR => OpenFlags::READ | OpenFlags::WRITE,
+        _ => OpenFlags::READ,
+    };
+
+    for (linux, flag) in [
+        (O_APPEND, OpenFlags::APPEND),
+        (O_CREAT, OpenFlags::CREATE),
+        (O_EXCL, OpenFlags::EXCL),
+        (O_TRUNC, OpenFlags::TRUNCATE),
+    ] {
+        if flags & linux != 0 {
+            open_flags |= flag;
+        }
+    }
+
+    open_flags
+}
+
+fn sys_open(path: u64, flags: u64) -> SyscallResult {
+    let path = read_user_cstr(path)?;
+    super::open_path(&path, open_flags(flags))
+}
+
+fn to_linux_stat(metadata: &Metadata) -> LinuxStat {
+    let kind = match metadata.kind {
+        FileKind::File => S_IFREG,
+        FileKind::Directory => S_IFDIR,
+        FileKind::Other => 0,
+    };
+
+    LinuxStat {
+        st_ino: metadata.inode,
+        st_nlink: 1,
+        st_mode: kind | metadata.perm as u32,
+        st_size: metadata.size as i64,
+        st_blksize: 4096,
+        st_blocks: metadata.size.div_ceil(512) as i64,
+        st_atime: Timespec { tv_sec: metadata.access_time as i64, tv_nsec: 0 },
+        st_mtime: Timespec { tv_sec: metadata.modif_time as i64, tv_nsec: 0 },
+        st_ctime: Timespec { tv_sec: metadata.creation_time as i64, tv_nsec: 0 },
+        ..LinuxStat::default()
+    }
+}
+
+fn sys_stat(path: u64, stat: u64) -> SyscallResult {
+    let path = read_user_cstr(path)?;
+    let metadata = fs::with_root(|fs| fs.metadata(Path::new(&path)))
+        .map_err(|e| errno::from_fs_error(&e))?;
+
+    write_user(stat, to_linux_stat(&metadata))?;
+    Ok(0)
+}
+
+fn sys_fstat(fd: u64, stat: u64) -> SyscallResult {
+    let path = match &*file_handle(fd)?.lock() {
+        FileDescription::File(open_file) => Some(open_file.path.clone()),
+        FileDescription::Port(_) => None,
+        FileDescription::Pipe(_) => {
+            write_user(stat, LinuxStat {
+                st_mode: S_IFIFO | 0o600,
+                st_nlink: 1,
+                st_blksize: PIPE_SIZE as i64,
+                ..LinuxStat::default()
+            })?;
+            return Ok(0);
+        }
+    };
+
+    let linux_stat = if let Some(path) = path {
+        let metadata = fs::with_root(|fs| fs.metadata(Path::new(&path)))
+            .map_err(|e| errno::from_fs_error(&e))?;
+        to_linux_stat(&metadata)
+    } else {
+        LinuxStat {
+            st_mode: S_IFCHR | 0o620,
+            st_nlink: 1,
+            st_blksize: 4096,
+            ..LinuxStat::default()
+        }
+    };
+
+    write_user(stat, linux_stat)?;
+    Ok(0)
+}
+
+// linux returns the current break when it can't be moved
+fn sys_brk(addr: u64) -> SyscallResult {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+
+    if let Ok(addr) = VirtAddr::try_new(addr)
+        && !addr.is_null()
+    {
+        process.memory.set_brk(addr);
+    }
+
+    Ok(process.memory.brk().as_u64())
+}
+
+fn read_iovecs(iov: u64, iovcnt: u64) -> Result<Vec<IoVec>, i64> {
+    const IOV_MAX: u64 = 1024;
+
+    if iovcnt > IOV_MAX {
+        return Err(errno::EINVAL);
+    }
+    let bytes = read_user_bytes(iov, iovcnt * core::mem::size_of::<IoVec>() as u64)?;
+
+    Ok(bytes
+        .chunks_exact(core::mem::size_of::<IoVec>())
+        .map(|chunk| unsafe { (chunk.as_ptr() as *const IoVec).read_unaligned() })
+        .collect())
+}
+
+// an error after some bytes went through only ends the transfer, the count
+// is returned like linux does
+fn sys_readv(fd: u64, iov: u64, iovcnt: u64) -> SyscallResult {
+    let mut total = 0;
+
+    for iovec in read_iovecs(iov, iovcnt)? {
+        let read = match super::sys_read(fd, iovec.base, iovec.len) {
+            Ok(read) => read,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += read;
+        if read < iovec.len {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+fn sys_writev(fd: u64, iov: u64, iovcnt: u64) -> SyscallResult {
+    let mut total = 0;
+
+    for iovec in read_iovecs(iov, iovcnt)? {
+        let written = match super::sys_write(fd, iovec.base, iovec.len) {
+            Ok(written) => written,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += written;
+        if written < iovec.len {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+// there are no signals, every handler is the default one and nothing is
+// blocked, so the old values are all zero
+fn sys_rt_sigaction(oldact: u64, sigsetsize: u64) -> SyscallResult {
+    // handler, flags and restorer before the mask
+    const SIGACTION_HEADER: u64 = 3 * 8;
+
+    if sigsetsize != SIGSET_SIZE {
+        return Err(errno::EINVAL);
+    }
+    if oldact != 0 {
+        copy_to_user(oldact, &[0; (SIGACTION_HEADER + SIGSET_SIZE) as usize])?;
+    }
+    Ok(0)
+}
+
+fn sys_rt_sigprocmask(oldset: u64, sigsetsize: u64) -> SyscallResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(errno::EINVAL);
+    }
+    if oldset != 0 {
+        copy_to_user(oldset, &[0; SIGSET_SIZE as usize])?;
+    }
+    Ok(0)
+}
+
+fn sys_uname(buf: u64) -> SyscallResult {
+    fn field(value: &str) -> [u8; 65] {
+        let mut field = [0; 65];
+        field[..value.len()].copy_from_slice(value.as_bytes());
+        field
+    }
+
+    write_user(buf, UtsName {
+        sysname: field("Illuminos"),
+        nodename: field("illuminos"),
+        release: field(env!("CARGO_PKG_VERSION")),
+        version: field("illuminos"),
+        machine: field("x86_64"),
+        domainname: field(""),
+    })?;
+
+    Ok(0)
+}
+
+fn sys_arch_prctl(code: u64, addr: u64) -> SyscallResult {
+    match code {
+        ARCH_SET_FS => {
+            let addr = VirtAddr::try_new(addr).map_err(|_| errno::EPERM)?;
+            FsBase::write(addr);
+            Ok(0)
+        }
+        ARCH_GET_FS => {
+            write_user(addr, FsBase::read().as_u64())?;
+            Ok(0)
+        }
+        _ => Err(errno::EINVAL),
+    }
+}
+
+fn sys_getdents64(fd: u64, buf: u64, count: u64) -> SyscallResult {
+    // d_ino, d_off, d_reclen and d_type before the name
+    const HEADER_SIZE: usize = 8 + 8 + 2 + 1;
+
+    super::getdents_with(fd, buf, count, |entry, next_offset| {
+        let name = entry.name.as_bytes();
+        let record_len = (HEADER_SIZE + name.len() + 1 + 7) & !7;
+
+        let mut record = Vec::with_capacity(record_len);
+        record.extend_from_slice(&entry.inode.to_le_bytes());
+        record.extend_from_slice(&next_offset.to_le_bytes());
+        record.extend_from_slice(&(record_len as u16).to_le_bytes());
+        record.push(match entry.kind {
+            FileKind::File => DT_REG,
+            FileKind::Directory => DT_DIR,
+            FileKind::Other => DT_UNKNOWN,
+        });
+        record.extend_from_slice(name);
+        record.resize(record_len, 0);
+        record
+    })
+}
+
+fn sys_clock_gettime(clock: u64, tp: u64) -> SyscallResult {
+    let uptime_ms = time::uptime_ms();
+    let timespec = match clock {
+        CLOCK_REALTIME => Timespec {
+            tv_sec: time::now() as i64,
+            tv_nsec: ((uptime_ms % 1000) * 1_000_000) as i64,
+        },
+        CLOCK_MONOTONIC => Timespec {
+            tv_sec: (uptime_ms / 1000) as i64,
+            tv_nsec: ((uptime_ms % 1000) * 1_000_000) as i64,
+        },
+        _ => return Err(errno::EINVAL),
+    };
+
+    write_user(tp, timespec)?;
+    Ok(0)
+}
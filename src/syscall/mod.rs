@@ -1,8 +1,82 @@
+pub mod errno;
+pub mod linux;
+pub mod trace;
+pub mod uaccess;
+
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{PageSize, Size4KiB};
 use x86_64::registers::model_specific::{Efer, EferFlags, Msr};
 use core::arch::global_asm;
+use crate::fs::{self, FileKind, OpenFlags, Path, SeekFrom};
+use crate::fs::page_cache::{FileRef, PAGE_CACHE, ROOT_FS_ID};
 use crate::gdt::GDT;
 use crate::println_serial;
-use crate::io::port::Fd;
+use crate::io::fd_table::{new_handle, FileDescription, FileHandle, OpenFile, MAX_FDS};
+use crate::io::pipe::{self, PipeEndKind};
+use crate::io::port::{Device, DeviceError, Fd, Readiness};
+use crate::thread::{self, Personality, PROCESS_TABLE};
+use crate::time;
+use crate::thread::shm::SHM;
+use crate::thread::vma::{Backing, Protection, VmaKind};
+use uaccess::{check_user_range, copy_to_user, read_user_bytes, read_user_str, write_user};
+
+// syscall number in rax, arguments in rdi, rsi, rdx, r10, r8, r9
+// the result is returned in rax, a negative value is an errno
+// same convention for `syscall` and `int 0x80`
+pub const SYS_READ: u64 = 1;
+pub const SYS_WRITE: u64 = 2;
+pub const SYS_OPEN: u64 = 3;
+pub const SYS_CLOSE: u64 = 4;
+pub const SYS_LSEEK: u64 = 5;
+pub const SYS_STAT: u64 = 6;
+pub const SYS_GETDENTS: u64 = 7;
+pub const SYS_GETPID: u64 = 8;
+pub const SYS_EXIT: u64 = 9;
+pub const SYS_BRK: u64 = 10;
+pub const SYS_MMAP: u64 = 11;
+pub const SYS_MUNMAP: u64 = 12;
+pub const SYS_MPROTECT: u64 = 13;
+pub const SYS_MSYNC: u64 = 14;
+pub const SYS_SHM_CREATE: u64 = 15;
+pub const SYS_SHM_OPEN: u64 = 16;
+pub const SYS_SHM_MAP: u64 = 17;
+pub const SYS_SHM_UNLINK: u64 = 18;
+pub const SYS_DUP: u64 = 19;
+pub const SYS_DUP2: u64 = 20;
+pub const SYS_PIPE: u64 = 21;
+pub const SYS_POLL: u64 = 22;
+pub const SYS_IOCTL: u64 = 23;
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+// mmap, same values as linux
+pub const PROT_NONE: u64 = 0x0;
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+// poll events, same values as linux
+pub const POLLIN: i16 = 0x01;
+pub const POLLOUT: i16 = 0x04;
+pub const POLLERR: i16 = 0x08;
+pub const POLLHUP: i16 = 0x10;
+pub const POLLNVAL: i16 = 0x20;
+
+pub const STAT_KIND_OTHER: u32 = 0;
+pub const STAT_KIND_FILE: u32 = 1;
+pub const STAT_KIND_DIR: u32 = 2;
+
+pub type SyscallResult = Result<u64, i64>;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -18,6 +92,50 @@ pub struct SyscallCtx {
     pub r11: u64,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stat {
+    pub inode: u64,
+    pub size: u64,
+    pub kind: u32,
+    pub perm: u32,
+    pub access_time: u64,
+    pub modif_time: u64,
+    pub creation_time: u64,
+}
+
+// same layout as linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+// a getdents record is this header followed by the nul terminated name,
+// padded so the next record is 8 bytes aligned
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dirent {
+    pub inode: u64,
+    pub record_len: u16,
+    pub kind: u8,
+    pub name_len: u8,
+}
+
+// longest read done at once, the data goes through a kernel buffer
+const MAX_IO: usize = 64 * 1024;
+
+const SYSCALL_STACK_SIZE: usize = 4096 * 5;
+
+#[repr(align(16))]
+struct SyscallStack([u8; SYSCALL_STACK_SIZE]);
+
+// syscall doesn't switch stacks, sys_handler moves to this one so the kernel
+// never runs on memory owned by the user
+static mut SYSCALL_STACK: SyscallStack = SyscallStack([0; SYSCALL_STACK_SIZE]);
+
 pub fn init_syscall() {
     let mut efer = Efer::read();
 
@@ -37,37 +155,586 @@ pub fn init_syscall() {
     unsafe {
         lstar.write(sys_handler_addr);
         star.write(0x0013000800000000u64);
-        sfmask.write(1 << 9);
+        // IF and AC, a user can't enter the kernel with SMAP lifted
+        sfmask.write(1 << 9 | 1 << 18);
+        syscall_kernel_rsp = &raw const SYSCALL_STACK as u64 + SYSCALL_STACK_SIZE as u64;
     }
 
+    uaccess::enable_protection();
 }
 
 #[unsafe(no_mangle)]
 extern "C" fn sys_dispatch(sys_ctx: *mut SyscallCtx) {
     let ctx = unsafe {
-        &*sys_ctx
+        &mut *sys_ctx
+    };
+
+    // int 0x80 keeps the user's rflags
+    uaccess::clear_user_access();
+
+    let (pid, personality, traced) = PROCESS_TABLE
+        .lock()
+        .current()
+        .map(|process| (process.pid(), process.personality(), process.traced()))
+        .unwrap_or((thread::current_pid(), Personality::Illuminos, false));
+
+    let trace = traced.then(|| trace::Trace::begin(pid, personality, ctx));
+
+    let result = match personality {
+        Personality::Illuminos => dispatch(ctx),
+        Personality::Linux => linux::dispatch(ctx),
+    };
+
+    if let Some(trace) = trace {
+        trace.end(&result);
+    }
+
+    ctx.syscall_id = match result {
+        Ok(value) => value,
+        Err(errno) => (-errno) as u64,
     };
+}
 
+fn dispatch(ctx: &SyscallCtx) -> SyscallResult {
     match ctx.syscall_id {
-        2 => {
-            let fd = ctx.rdi;
-            let buf = ctx.rsi as *const u8;
-            let buf_len = ctx.rdx;
+        SYS_READ => sys_read(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_WRITE => sys_write(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_OPEN => sys_open(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_CLOSE => sys_close(ctx.rdi),
+        SYS_LSEEK => sys_lseek(ctx.rdi, ctx.rsi as i64, ctx.rdx),
+        SYS_STAT => sys_stat(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_GETDENTS => sys_getdents(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_GETPID => Ok(thread::current_pid() as u64),
+        SYS_EXIT => thread::exit_current(ctx.rdi as i32),
+        SYS_BRK => sys_brk(ctx.rdi),
+        SYS_MMAP => sys_mmap(ctx.rdi, ctx.rsi, ctx.rdx, ctx.r10, ctx.r8, ctx.r9),
+        SYS_MUNMAP => sys_munmap(ctx.rdi, ctx.rsi),
+        SYS_MPROTECT => sys_mprotect(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_MSYNC => sys_msync(ctx.rdi, ctx.rsi),
+        SYS_SHM_CREATE => sys_shm_create(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_SHM_OPEN => sys_shm_open(ctx.rdi, ctx.rsi),
+        SYS_SHM_MAP => sys_shm_map(ctx.rdi, ctx.rsi, ctx.rdx),
+        SYS_SHM_UNLINK => sys_shm_unlink(ctx.rdi, ctx.rsi),
+        SYS_DUP => sys_dup(ctx.rdi),
+        SYS_DUP2 => sys_dup2(ctx.rdi, ctx.rsi),
+        SYS_PIPE => sys_pipe(ctx.rdi),
+        SYS_POLL => sys_poll(ctx.rdi, ctx.rsi, ctx.rdx as i32),
+        SYS_IOCTL => sys_ioctl(ctx.rdi, ctx.rsi, ctx.rdx),
+        e => {
+            println_serial!("unknown syscall {}", e);
+            Err(errno::ENOSYS)
+        }
+    }
+}
 
-            let slice = unsafe {
-                core::slice::from_raw_parts(buf, buf_len as usize)
-            };
+// open file description behind a descriptor of the current process, the
+// process table isn't kept locked while it is used
+fn file_handle(fd: u64) -> Result<FileHandle, i64> {
+    PROCESS_TABLE
+        .lock()
+        .current()
+        .and_then(|process| process.files.get(fd as usize))
+        .ok_or(errno::EBADF)
+}
+
+fn sys_read(fd: u64, buf: u64, count: u64) -> SyscallResult {
+    // checked before reading, a port would lose the data otherwise
+    let count = count.min(MAX_IO as u64);
+    check_user_range(buf, count, true)?;
 
-            let fd = Fd(fd as usize);
+    let mut data = vec![0; count as usize];
 
-            fd.write(slice);
+    let handle = file_handle(fd)?;
+    let mut description = handle.lock();
+    let open_file = match &mut *description {
+        FileDescription::File(open_file) => open_file,
+        FileDescription::Port(port) => {
+            let len = Fd(*port).read(&mut data, 0).map_err(errno::from_device_error)?;
+            copy_to_user(buf, &data[..len])?;
+            return Ok(len as u64);
+        }
+        FileDescription::Pipe(end) => {
+            if end.kind() != PipeEndKind::Read {
+                return Err(errno::EBADF);
+            }
+            let len = end.read(&mut data, 0).map_err(errno::from_device_error)?;
+            copy_to_user(buf, &data[..len])?;
+            return Ok(len as u64);
         }
+    };
 
-        e => {
-            println_serial!("{}", e);
+    if !open_file.file.flags().contains(OpenFlags::READ) {
+        return Err(errno::EBADF);
+    }
+
+    let len = open_file.file.read(&mut data).map_err(errno::from_device_error)?;
+    copy_to_user(buf, &data[..len])?;
+    Ok(len as u64)
+}
+
+fn sys_write(fd: u64, buf: u64, count: u64) -> SyscallResult {
+    // larger writes are short, like reads
+    let buf = read_user_bytes(buf, count.min(MAX_IO as u64))?;
+
+    let handle = file_handle(fd)?;
+    let mut description = handle.lock();
+    let open_file = match &mut *description {
+        FileDescription::File(open_file) => open_file,
+        FileDescription::Port(port) => {
+            let written = Fd(*port).write(&buf, 0).map_err(errno::from_device_error)?;
+            return Ok(written as u64);
+        }
+        FileDescription::Pipe(end) => {
+            if end.kind() != PipeEndKind::Write {
+                return Err(errno::EBADF);
+            }
+            let written = end.write(&buf, 0).map_err(errno::from_device_error)?;
+            return Ok(written as u64);
+        }
+    };
+
+    if !open_file.file.flags().contains(OpenFlags::WRITE) {
+        return Err(errno::EBADF);
+    }
+
+    // the file moves its cursor to the end itself with APPEND
+    let written = open_file.file.write(&buf).map_err(errno::from_device_error)?;
+    let offset = open_file.file.position() - written as u64;
+
+    // the cached pages are the ones mappings use, they get the new bytes too
+    if let Ok(metadata) = fs::with_root(|fs| fs.metadata(Path::new(&open_file.path))) {
+        PAGE_CACHE.lock().update(ROOT_FS_ID, metadata.inode, offset, &buf[..written]);
+    }
+
+    Ok(written as u64)
+}
+
+fn sys_open(path: u64, path_len: u64, flags: u64) -> SyscallResult {
+    let path = read_user_str(path, path_len)?;
+    open_path(&path, OpenFlags::from_bits_truncate(flags))
+}
+
+fn open_path(path: &str, flags: OpenFlags) -> SyscallResult {
+    let handle = match fs::devfs::port(path) {
+        Some(port) => new_handle(FileDescription::Port(port)),
+        None => {
+            let file = fs::open(path, flags).map_err(|e| errno::from_fs_error(&e))?;
+            new_handle(FileDescription::File(OpenFile {
+                file,
+                path: path.to_string(),
+            }))
+        }
+    };
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    process.files.insert(handle).map(|fd| fd as u64).ok_or(errno::EMFILE)
+}
+
+// the description is closed with its last descriptor
+fn sys_close(fd: u64) -> SyscallResult {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    process.files.close(fd as usize).map(|_| 0).ok_or(errno::EBADF)
+}
+
+fn sys_dup(fd: u64) -> SyscallResult {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    let fd = fd as usize;
+
+    if process.files.get(fd).is_none() {
+        return Err(errno::EBADF);
+    }
+    process.files.dup(fd).map(|fd| fd as u64).ok_or(errno::EMFILE)
+}
+
+fn sys_dup2(old: u64, new: u64) -> SyscallResult {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    process.files
+        .dup2(old as usize, new as usize)
+        .map(|fd| fd as u64)
+        .ok_or(errno::EBADF)
+}
+
+// fds[0] is the read end and fds[1] the write end, as two ints
+fn sys_pipe(fds: u64) -> SyscallResult {
+    check_user_range(fds, core::mem::size_of::<[i32; 2]>() as u64, true)?;
+
+    let (reader, writer) = pipe::pipe();
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    let read_fd = process.files
+        .insert(new_handle(FileDescription::Pipe(reader)))
+        .ok_or(errno::EMFILE)?;
+    let Some(write_fd) = process.files.insert(new_handle(FileDescription::Pipe(writer))) else {
+        process.files.close(read_fd);
+        return Err(errno::EMFILE);
+    };
+    drop(table);
+
+    write_user(fds, [read_fd as i32, write_fd as i32])?;
+    Ok(0)
+}
+
+fn poll_events(handle: Option<&FileHandle>, events: i16) -> i16 {
+    let Some(handle) = handle else {
+        return POLLNVAL;
+    };
+
+    let readiness = handle.lock().readiness();
+    let mut revents = 0;
+    if readiness.contains(Readiness::READABLE) {
+        revents |= POLLIN;
+    }
+    if readiness.contains(Readiness::WRITABLE) {
+        revents |= POLLOUT;
+    }
+    // hang ups are reported even when not asked for
+    (revents & events) | if readiness.contains(Readiness::HANGUP) { POLLHUP } else { 0 }
+}
+
+// waits until one of the descriptors is ready or `timeout` ms went by, a
+// negative timeout waits forever. returns how many are ready
+fn sys_poll(fds: u64, nfds: u64, timeout: i32) -> SyscallResult {
+    if nfds as usize > MAX_FDS {
+        return Err(errno::EINVAL);
+    }
+    let len = nfds * core::mem::size_of::<PollFd>() as u64;
+    check_user_range(fds, len, true)?;
+
+    let mut polls: Vec<PollFd> = read_user_bytes(fds, len)?
+        .chunks_exact(core::mem::size_of::<PollFd>())
+        .map(|bytes| unsafe { (bytes.as_ptr() as *const PollFd).read_unaligned() })
+        .collect();
+
+    // negative descriptors are skipped
+    let handles: Vec<Option<FileHandle>> = polls
+        .iter()
+        .map(|poll| if poll.fd < 0 { None } else { file_handle(poll.fd as u64).ok() })
+        .collect();
+
+    let deadline = (timeout >= 0).then(|| time::uptime_ms() + timeout as u64);
+    let ready = loop {
+        let mut ready = 0;
+        for (poll, handle) in polls.iter_mut().zip(&handles) {
+            poll.revents = if poll.fd < 0 { 0 } else { poll_events(handle.as_ref(), poll.events) };
+            if poll.revents != 0 {
+                ready += 1;
+            }
+        }
+
+        if ready > 0 || deadline.is_some_and(|deadline| time::uptime_ms() >= deadline) {
+            break ready;
+        }
+        // the timer interrupt wakes it up at the latest
+        thread::wait_for_interrupt();
+    };
+
+    for (i, poll) in polls.iter().enumerate() {
+        write_user(fds + (i * core::mem::size_of::<PollFd>()) as u64, *poll)?;
+    }
+    Ok(ready)
+}
+
+fn sys_lseek(fd: u64, offset: i64, whence: u64) -> SyscallResult {
+    let handle = file_handle(fd)?;
+    let mut description = handle.lock();
+    let FileDescription::File(open_file) = &mut *description else {
+        return Err(errno::ESPIPE);
+    };
+
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return Err(errno::EINVAL),
+    };
+
+    open_file.file.seek(pos).map_err(errno::from_device_error)
+}
+
+// only devices take requests, files never do
+fn sys_ioctl(fd: u64, request: u64, arg: u64) -> SyscallResult {
+    let handle = file_handle(fd)?;
+    let FileDescription::Port(port) = *handle.lock() else {
+        return Err(errno::ENOTTY);
+    };
+
+    Fd(port).ioctl(request, arg).map_err(|e| match e {
+        DeviceError::NotSupported => errno::ENOTTY,
+        e => errno::from_device_error(e),
+    })
+}
+
+fn sys_stat(path: u64, path_len: u64, stat: u64) -> SyscallResult {
+    let path = read_user_str(path, path_len)?;
+    let metadata = fs::with_root(|fs| fs.metadata(Path::new(&path)))
+        .map_err(|e| errno::from_fs_error(&e))?;
+
+    write_user(stat, Stat {
+        inode: metadata.inode,
+        size: metadata.size,
+        kind: stat_kind(metadata.kind),
+        perm: metadata.perm as u32,
+        access_time: metadata.access_time,
+        modif_time: metadata.modif_time,
+        creation_time: metadata.creation_time,
+    })?;
+
+    Ok(0)
+}
+
+fn stat_kind(kind: FileKind) -> u32 {
+    match kind {
+        FileKind::File => STAT_KIND_FILE,
+        FileKind::Directory => STAT_KIND_DIR,
+        FileKind::Other => STAT_KIND_OTHER,
+    }
+}
+
+fn sys_getdents(fd: u64, buf: u64, count: u64) -> SyscallResult {
+    let header_size = core::mem::size_of::<Dirent>();
+
+    getdents_with(fd, buf, count, |entry, _| {
+        let name = entry.name.as_bytes();
+        let name_len = name.len().min(u8::MAX as usize);
+        let record_len = (header_size + name_len + 1 + 7) & !7;
+
+        let header = Dirent {
+            inode: entry.inode,
+            record_len: record_len as u16,
+            kind: stat_kind(entry.kind) as u8,
+            name_len: name_len as u8,
+        };
+
+        let mut record = vec![0; record_len];
+        unsafe {
+            (record.as_mut_ptr() as *mut Dirent).write_unaligned(header);
+        }
+        record[header_size..header_size + name_len].copy_from_slice(&name[..name_len]);
+        record
+    })
+}
+
+// the offset of a directory is the index of the next entry to return,
+// `encode` turns an entry into a record of the caller's ABI, it gets the
+// offset right after the entry
+fn getdents_with<F>(fd: u64, buf: u64, count: u64, mut encode: F) -> SyscallResult
+where
+    F: FnMut(&fs::DirEntry, u64) -> Vec<u8>,
+{
+    check_user_range(buf, count, true)?;
+
+    let handle = file_handle(fd)?;
+    let mut description = handle.lock();
+    let FileDescription::File(open_file) = &mut *description else {
+        return Err(errno::ENOTDIR);
+    };
+    let entries = fs::with_root(|fs| fs.read_dir(Path::new(&open_file.path)))
+        .map_err(|e| errno::from_fs_error(&e))?;
+
+    let mut out = Vec::new();
+
+    let mut next = open_file.file.position();
+    for entry in entries.iter().skip(next as usize) {
+        let record = encode(entry, next + 1);
+
+        if out.len() + record.len() > count as usize {
+            if out.is_empty() {
+                return Err(errno::EINVAL);
+            }
+            break;
         }
 
+        out.extend_from_slice(&record);
+        next += 1;
     }
+
+    copy_to_user(buf, &out)?;
+    let _ = open_file.file.seek(SeekFrom::Start(next));
+    Ok(out.len() as u64)
+}
+
+fn sys_brk(addr: u64) -> SyscallResult {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+
+    if addr == 0 {
+        return Ok(process.memory.brk().as_u64());
+    }
+
+    let addr = VirtAddr::try_new(addr).map_err(|_| errno::ENOMEM)?;
+    process.memory
+        .set_brk(addr)
+        .map(|brk| brk.as_u64())
+        .ok_or(errno::ENOMEM)
+}
+
+// page aligned user range, the length is rounded up to whole pages
+fn page_range(addr: u64, len: u64) -> Result<(VirtAddr, u64), i64> {
+    if len == 0 || !addr.is_multiple_of(Size4KiB::SIZE) {
+        return Err(errno::EINVAL);
+    }
+
+    let len = len.checked_next_multiple_of(Size4KiB::SIZE).ok_or(errno::EINVAL)?;
+    let end = addr.checked_add(len).ok_or(errno::EINVAL)?;
+    if end > uaccess::USER_SPACE_END {
+        return Err(errno::EINVAL);
+    }
+
+    Ok((VirtAddr::new(addr), len))
+}
+
+// file to map, the descriptor has to allow what the mapping allows
+fn mmap_file(fd: u64, prot: Protection, shared: bool) -> Result<FileRef, i64> {
+    let handle = file_handle(fd)?;
+    let description = handle.lock();
+    let FileDescription::File(open_file) = &*description else {
+        return Err(errno::ENODEV);
+    };
+    let open_flags = open_file.file.flags();
+
+    if !open_flags.contains(OpenFlags::READ)
+        || (shared && prot.contains(Protection::WRITE) && !open_flags.contains(OpenFlags::WRITE))
+    {
+        return Err(errno::EACCES);
+    }
+
+    let metadata = fs::with_root(|fs| fs.metadata(Path::new(&open_file.path)))
+        .map_err(|e| errno::from_fs_error(&e))?;
+    if metadata.kind != FileKind::File {
+        return Err(errno::ENODEV);
+    }
+
+    Ok(FileRef { path: open_file.path.clone(), inode: metadata.inode })
+}
+
+fn sys_mmap(addr: u64, len: u64, prot: u64, flags: u64, fd: u64, offset: u64) -> SyscallResult {
+    if len == 0 || flags & (MAP_SHARED | MAP_PRIVATE) == 0 {
+        return Err(errno::EINVAL);
+    }
+    let prot = Protection::from_bits(prot).ok_or(errno::EINVAL)?;
+    let len = len.checked_next_multiple_of(Size4KiB::SIZE).ok_or(errno::ENOMEM)?;
+    let shared = flags & MAP_SHARED != 0;
+
+    let file = if flags & MAP_ANONYMOUS == 0 {
+        if !offset.is_multiple_of(Size4KiB::SIZE) {
+            return Err(errno::EINVAL);
+        }
+        Some(mmap_file(fd, prot, shared)?)
+    } else {
+        None
+    };
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+
+    let start = if flags & MAP_FIXED != 0 {
+        let (start, len) = page_range(addr, len)?;
+        // the stack guard stays, an overflow would go unnoticed otherwise
+        if process.memory.vmas().has_guard(start, start + len) {
+            return Err(errno::EINVAL);
+        }
+        process.memory.unmap_region(start, len);
+        start
+    } else {
+        let hint = VirtAddr::try_new(addr)
+            .ok()
+            .filter(|hint| !hint.is_null() && hint.is_aligned(Size4KiB::SIZE));
+        process.memory.find_free_region(len, hint).ok_or(errno::ENOMEM)?
+    };
+
+    let mapped = match file {
+        Some(file) => process.memory.map_file(start, len, prot, VmaKind::Mmap, Backing::File { file, offset, shared }),
+        None => process.memory.map_region(start, len, prot, VmaKind::Mmap, Backing::Anonymous),
+    };
+
+    mapped.map(|addr| addr.as_u64()).ok_or(errno::ENOMEM)
+}
+
+fn sys_msync(addr: u64, len: u64) -> SyscallResult {
+    let (start, len) = page_range(addr, len)?;
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    process.memory
+        .sync_region(start, len)
+        .map(|_| 0)
+        .ok_or(errno::EIO)
+}
+
+fn sys_munmap(addr: u64, len: u64) -> SyscallResult {
+    let (start, len) = page_range(addr, len)?;
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    if process.memory.vmas().has_guard(start, start + len) {
+        return Err(errno::EINVAL);
+    }
+    process.memory.unmap_region(start, len);
+
+    Ok(0)
+}
+
+fn sys_mprotect(addr: u64, len: u64, prot: u64) -> SyscallResult {
+    let (start, len) = page_range(addr, len)?;
+    let prot = Protection::from_bits(prot).ok_or(errno::EINVAL)?;
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+    process.memory
+        .protect_region(start, len, prot)
+        .map(|_| 0)
+        .ok_or(errno::ENOMEM)
+}
+
+// shared memory objects are named globally and referred to by id
+fn sys_shm_create(name: u64, name_len: u64, size: u64) -> SyscallResult {
+    let name = read_user_str(name, name_len)?;
+    if size == 0 {
+        return Err(errno::EINVAL);
+    }
+
+    let mut shm = SHM.lock();
+    if shm.open(&name).is_some() {
+        return Err(errno::EEXIST);
+    }
+    shm.create(&name, size).map(|id| id as u64).ok_or(errno::ENOMEM)
+}
+
+fn sys_shm_open(name: u64, name_len: u64) -> SyscallResult {
+    let name = read_user_str(name, name_len)?;
+    SHM.lock().open(&name).map(|id| id as u64).ok_or(errno::ENOENT)
+}
+
+// maps the whole object, `addr` is a hint like for mmap
+fn sys_shm_map(id: u64, addr: u64, prot: u64) -> SyscallResult {
+    let prot = Protection::from_bits(prot).ok_or(errno::EINVAL)?;
+
+    // held until mapped, so an unlink can't free the frames in between
+    let shm = SHM.lock();
+    let object = shm.get(id as usize).ok_or(errno::ENOENT)?;
+
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.current().ok_or(errno::ESRCH)?;
+
+    let hint = VirtAddr::try_new(addr)
+        .ok()
+        .filter(|hint| !hint.is_null() && hint.is_aligned(Size4KiB::SIZE));
+    let start = process.memory.find_free_region(object.len(), hint).ok_or(errno::ENOMEM)?;
+
+    process.memory
+        .map_shared(start, &object.frames, prot, id as usize)
+        .map(|addr| addr.as_u64())
+        .ok_or(errno::ENOMEM)
+}
+
+fn sys_shm_unlink(name: u64, name_len: u64) -> SyscallResult {
+    let name = read_user_str(name, name_len)?;
+    SHM.lock().unlink(&name).map(|_| 0).ok_or(errno::ENOENT)
 }
 
 global_asm!(
@@ -81,4 +748,6 @@ global_asm!(
 
 unsafe extern "C" {
     fn sys_handler();
+    pub fn int80_handler();
+    static mut syscall_kernel_rsp: u64;
 }
@@ -0,0 +1,191 @@
+// strace like logging of the syscalls of traced processes
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::x86_64::_rdtsc;
+use core::fmt::Write;
+
+use super::{errno, linux, SyscallCtx, SyscallResult};
+use super::uaccess::{read_user_bytes, read_user_cstr};
+use crate::println_serial;
+use crate::thread::Personality;
+
+// longest buffer shown, like strace's default
+const MAX_SHOWN: usize = 32;
+
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Ptr,
+    // nul terminated string
+    CStr,
+    // string or input buffer whose length is the argument at this index
+    Str(usize),
+}
+
+use Arg::*;
+
+// name and arguments of a syscall
+fn signature(personality: Personality, id: u64) -> Option<(&'static str, &'static [Arg])> {
+    let signature: (&'static str, &'static [Arg]) = match personality {
+        Personality::Illuminos => match id {
+            super::SYS_READ => ("read", &[Int, Ptr, Int]),
+            super::SYS_WRITE => ("write", &[Int, Str(2), Int]),
+            super::SYS_OPEN => ("open", &[Str(1), Int, Ptr]),
+            super::SYS_CLOSE => ("close", &[Int]),
+            super::SYS_LSEEK => ("lseek", &[Int, Int, Int]),
+            super::SYS_STAT => ("stat", &[Str(1), Int, Ptr]),
+            super::SYS_GETDENTS => ("getdents", &[Int, Ptr, Int]),
+            super::SYS_GETPID => ("getpid", &[]),
+            super::SYS_EXIT => ("exit", &[Int]),
+            super::SYS_BRK => ("brk", &[Ptr]),
+            super::SYS_MMAP => ("mmap", &[Ptr, Int, Ptr, Ptr, Int, Int]),
+            super::SYS_MUNMAP => ("munmap", &[Ptr, Int]),
+            super::SYS_MPROTECT => ("mprotect", &[Ptr, Int, Ptr]),
+            super::SYS_MSYNC => ("msync", &[Ptr, Int, Ptr]),
+            super::SYS_SHM_CREATE => ("shm_create", &[Str(1), Int, Int]),
+            super::SYS_SHM_OPEN => ("shm_open", &[Str(1), Int]),
+            super::SYS_SHM_MAP => ("shm_map", &[Int, Ptr, Ptr]),
+            super::SYS_SHM_UNLINK => ("shm_unlink", &[Str(1), Int]),
+            super::SYS_DUP => ("dup", &[Int]),
+            super::SYS_DUP2 => ("dup2", &[Int, Int]),
+            super::SYS_PIPE => ("pipe", &[Ptr]),
+            super::SYS_POLL => ("poll", &[Ptr, Int, Int]),
+            super::SYS_IOCTL => ("ioctl", &[Int, Ptr, Ptr]),
+            _ => return None,
+        },
+        Personality::Linux => match id {
+            linux::SYS_READ => ("read", &[Int, Ptr, Int]),
+            linux::SYS_WRITE => ("write", &[Int, Str(2), Int]),
+            linux::SYS_OPEN => ("open", &[CStr, Ptr, Ptr]),
+            linux::SYS_CLOSE => ("close", &[Int]),
+            linux::SYS_STAT => ("stat", &[CStr, Ptr]),
+            linux::SYS_FSTAT => ("fstat", &[Int, Ptr]),
+            linux::SYS_LSTAT => ("lstat", &[CStr, Ptr]),
+            linux::SYS_POLL => ("poll", &[Ptr, Int, Int]),
+            linux::SYS_LSEEK => ("lseek", &[Int, Int, Int]),
+            linux::SYS_MMAP => ("mmap", &[Ptr, Int, Ptr, Ptr, Int, Int]),
+            linux::SYS_MPROTECT => ("mprotect", &[Ptr, Int, Ptr]),
+            linux::SYS_MUNMAP => ("munmap", &[Ptr, Int]),
+            linux::SYS_BRK => ("brk", &[Ptr]),
+            linux::SYS_RT_SIGACTION => ("rt_sigaction", &[Int, Ptr, Ptr, Int]),
+            linux::SYS_RT_SIGPROCMASK => ("rt_sigprocmask", &[Int, Ptr, Ptr, Int]),
+            linux::SYS_IOCTL => ("ioctl", &[Int, Ptr, Ptr]),
+            linux::SYS_READV => ("readv", &[Int, Ptr, Int]),
+            linux::SYS_WRITEV => ("writev", &[Int, Ptr, Int]),
+            linux::SYS_MSYNC => ("msync", &[Ptr, Int, Ptr]),
+            linux::SYS_PIPE => ("pipe", &[Ptr]),
+            linux::SYS_DUP => ("dup", &[Int]),
+            linux::SYS_DUP2 => ("dup2", &[Int, Int]),
+            linux::SYS_GETPID => ("getpid", &[]),
+            linux::SYS_EXIT => ("exit", &[Int]),
+            linux::SYS_UNAME => ("uname", &[Ptr]),
+            linux::SYS_GETUID => ("getuid", &[]),
+            linux::SYS_GETGID => ("getgid", &[]),
+            linux::SYS_GETEUID => ("geteuid", &[]),
+            linux::SYS_GETEGID => ("getegid", &[]),
+            linux::SYS_ARCH_PRCTL => ("arch_prctl", &[Ptr, Ptr]),
+            linux::SYS_GETTID => ("gettid", &[]),
+            linux::SYS_GETDENTS64 => ("getdents64", &[Int, Ptr, Int]),
+            linux::SYS_SET_TID_ADDRESS => ("set_tid_address", &[Ptr]),
+            linux::SYS_CLOCK_GETTIME => ("clock_gettime", &[Int, Ptr]),
+            linux::SYS_EXIT_GROUP => ("exit_group", &[Int]),
+            _ => return None,
+        },
+    };
+
+    Some(signature)
+}
+
+fn escape(bytes: &[u8], out: &mut String) {
+    out.push('"');
+    for &byte in bytes.iter().take(MAX_SHOWN) {
+        match byte {
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7E => out.push(byte as char),
+            _ => { let _ = write!(out, "\\x{:02x}", byte); }
+        }
+    }
+    out.push('"');
+    if bytes.len() > MAX_SHOWN {
+        out.push_str("...");
+    }
+}
+
+fn format_arg(arg: Arg, args: &[u64; 6], value: u64, out: &mut String) {
+    match arg {
+        Int => { let _ = write!(out, "{}", value as i64); }
+        Ptr => { let _ = write!(out, "{:#x}", value); }
+        CStr => match read_user_cstr(value) {
+            Ok(s) => escape(s.as_bytes(), out),
+            Err(_) => { let _ = write!(out, "{:#x}", value); }
+        },
+        Str(len_index) => {
+            // one byte more than shown, to know if it was cut
+            let len = args[len_index].min(MAX_SHOWN as u64 + 1);
+            match read_user_bytes(value, len) {
+                Ok(bytes) => escape(&bytes, out),
+                Err(_) => { let _ = write!(out, "{:#x}", value); }
+            }
+        }
+    }
+}
+
+pub struct Trace {
+    pid: usize,
+    call: String,
+    start: u64,
+}
+
+impl Trace {
+    pub fn begin(pid: usize, personality: Personality, ctx: &SyscallCtx) -> Trace {
+        let args = [ctx.rdi, ctx.rsi, ctx.rdx, ctx.r10, ctx.r8, ctx.r9];
+        let mut call = String::new();
+
+        match signature(personality, ctx.syscall_id) {
+            Some((name, arg_kinds)) => {
+                call.push_str(name);
+                call.push('(');
+                for (i, arg) in arg_kinds.iter().enumerate() {
+                    if i > 0 {
+                        call.push_str(", ");
+                    }
+                    format_arg(*arg, &args, args[i], &mut call);
+                }
+                call.push(')');
+
+                // exit never comes back to `end`
+                if name == "exit" || name == "exit_group" {
+                    println_serial!("[pid {}] {} = ?", pid, call);
+                }
+            }
+            None => {
+                let args: Vec<String> = args.iter().map(|arg| format!("{:#x}", arg)).collect();
+                let _ = write!(call, "syscall_{}({})", ctx.syscall_id, args.join(", "));
+            }
+        }
+
+        Trace {
+            pid,
+            call,
+            start: unsafe { _rdtsc() },
+        }
+    }
+
+    pub fn end(self, result: &SyscallResult) {
+        let cycles = unsafe { _rdtsc() } - self.start;
+
+        match result {
+            Ok(value) => println_serial!(
+                "[pid {}] {} = {} <{} cycles>", self.pid, self.call, value, cycles
+            ),
+            Err(e) => println_serial!(
+                "[pid {}] {} = -1 {} <{} cycles>", self.pid, self.call, errno::name(*e), cycles
+            ),
+        }
+    }
+}
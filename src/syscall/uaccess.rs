@@ -0,0 +1,205 @@
+// checked access to user memory from syscalls
+//
+// every range is checked against the active page table before being touched,
+// so a bad pointer gives EFAULT instead of a kernel page fault
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::VirtAddr;
+use x86_64::registers::control::{Cr3, Cr4, Cr4Flags};
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+
+use super::errno;
+use crate::allocator::paging::physical_memory_offset;
+use crate::{info, thread};
+
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+pub const PATH_MAX: usize = 4096;
+
+const CPUID_SMEP: u32 = 1 << 7;
+const CPUID_SMAP: u32 = 1 << 20;
+
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_protection() {
+    let features = unsafe { __cpuid_count(7, 0) }.ebx;
+    let mut flags = Cr4Flags::empty();
+
+    if features & CPUID_SMEP != 0 {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if features & CPUID_SMAP != 0 {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+        SMAP_ENABLED.store(true, Ordering::SeqCst);
+    }
+
+    unsafe {
+        Cr4::update(|cr4| cr4.insert(flags));
+    }
+
+    info!("SMEP: {}, SMAP: {}", features & CPUID_SMEP != 0, features & CPUID_SMAP != 0);
+}
+
+// run `f` with SMAP lifted, the only place where the kernel may touch pages
+// mapped USER_ACCESSIBLE
+pub fn with_user_access<R>(f: impl FnOnce() -> R) -> R {
+    let smap = SMAP_ENABLED.load(Ordering::SeqCst);
+
+    if smap {
+        unsafe { asm!("stac", options(nostack)) };
+    }
+    let result = f();
+    if smap {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+
+    result
+}
+
+pub fn clear_user_access() {
+    if SMAP_ENABLED.load(Ordering::SeqCst) {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+}
+
+// (user accessible, writable) for a mapped address of the active page table
+fn page_access(addr: VirtAddr) -> Option<(bool, bool)> {
+    let phys_offset = physical_memory_offset();
+    let mut table_addr = Cr3::read().0.start_address();
+    let mut user = true;
+    let mut writable = true;
+
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    for index in indexes {
+        let table = unsafe { &*(phys_offset + table_addr.as_u64()).as_ptr::<PageTable>() };
+        let entry = &table[index];
+        let flags = entry.flags();
+
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+
+        user &= flags.contains(PageTableFlags::USER_ACCESSIBLE);
+        writable &= flags.contains(PageTableFlags::WRITABLE);
+
+        if flags.contains(PageTableFlags::HUGE_PAGE) {
+            break;
+        }
+        table_addr = entry.addr();
+    }
+
+    Some((user, writable))
+}
+
+fn accessible(page: VirtAddr, write: bool) -> bool {
+    matches!(page_access(page), Some((true, writable)) if writable || !write)
+}
+
+pub fn check_user_range(addr: u64, len: u64, write: bool) -> Result<(), i64> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let end = addr.checked_add(len).ok_or(errno::EFAULT)?;
+    if addr == 0 || end > USER_SPACE_END {
+        return Err(errno::EFAULT);
+    }
+
+    let mut page = addr & !0xFFF;
+    while page < end {
+        // pages mapped on first touch are brought in like the cpu would do
+        if !accessible(VirtAddr::new(page), write)
+            && !(thread::handle_page_fault(VirtAddr::new(page), write) && accessible(VirtAddr::new(page), write))
+        {
+            return Err(errno::EFAULT);
+        }
+        page += 0x1000;
+    }
+
+    Ok(())
+}
+
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), i64> {
+    check_user_range(src, dst.len() as u64, false)?;
+
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(src as *const u8, dst.as_mut_ptr(), dst.len());
+    });
+
+    Ok(())
+}
+
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), i64> {
+    check_user_range(dst, src.len() as u64, true)?;
+
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len());
+    });
+
+    Ok(())
+}
+
+pub fn read_user_bytes(addr: u64, len: u64) -> Result<Vec<u8>, i64> {
+    // a length no user mapping covers must not reach the allocator
+    check_user_range(addr, len, false)?;
+    let mut buf = vec![0; len as usize];
+    copy_from_user(&mut buf, addr)?;
+    Ok(buf)
+}
+
+pub fn read_user<T: Copy>(addr: u64) -> Result<T, i64> {
+    check_user_range(addr, core::mem::size_of::<T>() as u64, false)?;
+
+    Ok(with_user_access(|| unsafe { (addr as *const T).read_unaligned() }))
+}
+
+pub fn write_user<T>(addr: u64, value: T) -> Result<(), i64> {
+    check_user_range(addr, core::mem::size_of::<T>() as u64, true)?;
+
+    with_user_access(|| unsafe { (addr as *mut T).write_unaligned(value) });
+    Ok(())
+}
+
+pub fn read_user_str(addr: u64, len: u64) -> Result<String, i64> {
+    if len as usize > PATH_MAX {
+        return Err(errno::ENAMETOOLONG);
+    }
+
+    String::from_utf8(read_user_bytes(addr, len)?).map_err(|_| errno::EINVAL)
+}
+
+// nul terminated string, checked one page at a time
+pub fn read_user_cstr(addr: u64) -> Result<String, i64> {
+    let mut bytes = Vec::new();
+    let mut current = addr;
+
+    loop {
+        let page_end = (current & !0xFFF) + 0x1000;
+        check_user_range(current, page_end - current, false)?;
+
+        let found = with_user_access(|| {
+            while current < page_end {
+                let byte = unsafe { *(current as *const u8) };
+                current += 1;
+                if byte == 0 {
+                    return true;
+                }
+                bytes.push(byte);
+            }
+            false
+        });
+
+        if found {
+            break;
+        }
+        if bytes.len() >= PATH_MAX {
+            return Err(errno::ENAMETOOLONG);
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| errno::EINVAL)
+}
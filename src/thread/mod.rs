@@ -2,19 +2,38 @@ use core::arch::asm;
 use x86_64::{VirtAddr, PhysAddr};
 use core::alloc::Layout;
 use alloc::vec::Vec;
-use crate::allocator::{memory::{HEAP_SIZE, HEAP_START, reserve_memory}, paging::PagingManager};
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use crate::allocator::{memory::{HEAP_SIZE, HEAP_START}, paging::{kernel_page_table, kernel_page_table_frame, KernelFrameAllocator, PagingManager, KERNEL_HALF_START}};
 use core::arch::global_asm;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use crate::math;
-use x86_64::structures::paging::{Size4KiB, PageSize, PhysFrame, PageTableFlags, OffsetPageTable, Page, PageTable, Mapper, Translate};
+use x86_64::structures::paging::{Size4KiB, PageSize, PhysFrame, PageTableFlags, OffsetPageTable, Page, PageTable, Mapper, Translate, FrameAllocator, FrameDeallocator};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use crate::fs::{self, page_cache::{FileRef, PAGE_CACHE}};
+use crate::io::fd_table::FdTable;
+use crate::io::port::STDIO;
 use x86_64::registers::control::{Cr3, Cr3Flags};
-use crate::error;
+use crate::{error, info};
 use crate::println_serial;
 
 use crate::gdt::GDT;
 
+pub mod oom;
+pub mod shm;
+pub mod swap;
+pub mod vma;
+
+use vma::{Backing, Protection, Vma, VmaKind, VmaList};
+
 pub static PID: AtomicUsize = AtomicUsize::new(1);
 
+// pid of the process running in ring 3, 0 when the kernel is running
+pub static CURRENT_PID: AtomicUsize = AtomicUsize::new(0);
+
+pub static PROCESS_TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable::new());
+
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Pid(usize);
@@ -25,6 +44,75 @@ impl Pid {
         PID.fetch_add(1, Ordering::SeqCst);
         Pid(pid)
     }
+
+    pub fn as_usize(&self) -> usize {
+        self.0
+    }
+}
+
+pub fn current_pid() -> usize {
+    CURRENT_PID.load(Ordering::SeqCst)
+}
+
+pub struct ProcessTable {
+    processes: BTreeMap<usize, Process<'static>>,
+    // last process reclaim took pages from
+    reclaim_hand: usize,
+}
+
+impl ProcessTable {
+    pub const fn new() -> Self {
+        ProcessTable { processes: BTreeMap::new(), reclaim_hand: 0 }
+    }
+
+    pub fn add(&mut self, process: Process<'static>) -> usize {
+        let pid = process.pid();
+        self.processes.insert(pid, process);
+        pid
+    }
+
+    pub fn get(&self, pid: usize) -> Option<&Process<'static>> {
+        self.processes.get(&pid)
+    }
+
+    pub fn get_mut(&mut self, pid: usize) -> Option<&mut Process<'static>> {
+        self.processes.get_mut(&pid)
+    }
+
+    pub fn current(&mut self) -> Option<&mut Process<'static>> {
+        self.processes.get_mut(&current_pid())
+    }
+
+    pub fn remove(&mut self, pid: usize) -> Option<Process<'static>> {
+        self.processes.remove(&pid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    // swaps out up to `count` pages, the processes are visited in turn from
+    // the one after the last reclaimed from. returns the frames freed
+    pub fn reclaim(&mut self, count: usize) -> usize {
+        let pids: Vec<usize> = self.processes
+            .range(self.reclaim_hand + 1..)
+            .chain(self.processes.range(..=self.reclaim_hand))
+            .map(|(pid, _)| *pid)
+            .collect();
+
+        let mut freed = 0;
+        for pid in pids {
+            if freed >= count {
+                break;
+            }
+            self.reclaim_hand = pid;
+            if let Some(process) = self.processes.get_mut(&pid) {
+                freed += process.memory.reclaim(count - freed);
+            }
+        }
+
+        freed
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -34,30 +122,55 @@ pub struct Stack {
 }
 
 
+// kernel thread stacks are mapped here, each one above an unmapped guard page
+const KERNEL_STACKS_START: u64 = 0xFFFF_A000_0000_0000;
+const GUARD_SIZE: u64 = Size4KiB::SIZE;
+
+static NEXT_KERNEL_STACK: AtomicU64 = AtomicU64::new(KERNEL_STACKS_START);
+
+// guard page -> thread owning the stack above it
+static KERNEL_STACK_GUARDS: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
+
 impl Stack {
-    pub fn allocate(size: usize) -> Stack {
-        let ptr = unsafe {
-            alloc::alloc::alloc(
-                Layout::from_size_align(
-                    size,
-                    16
-                ).unwrap()
-            )
-        };
+    pub fn allocate(size: usize, tid: usize) -> Stack {
+        let size = (size as u64).next_multiple_of(Size4KiB::SIZE);
+        let guard = NEXT_KERNEL_STACK.fetch_add(GUARD_SIZE + size, Ordering::SeqCst);
+        let stack_base = guard + GUARD_SIZE;
 
+        let mut mapper = kernel_page_table();
+        let mut frame_allocator = KernelFrameAllocator::shared();
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(VirtAddr::new(stack_base)),
+            Page::containing_address(VirtAddr::new(stack_base + size)),
+        );
+        for page in pages {
+            let frame = frame_allocator.allocate_frame().expect("no frame for a kernel stack");
+            unsafe {
+                mapper
+                    .map_to(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE, &mut frame_allocator)
+                    .expect("kernel stack mapping failed")
+                    .flush();
+            }
+        }
 
-        let stack_base = ptr.addr() as u64;
-        let stack_top = stack_base + size as u64;
+        KERNEL_STACK_GUARDS.lock().insert(guard, tid);
 
         Stack {
             stack_base,
-            stack_top
+            stack_top: stack_base + size,
         }
     }
 
+    // the page at `addr` is the guard, the stack is the `page_count` pages
+    // above it. None when something is mapped at the guard, it would be lost
     pub fn allocate_with(pm: &mut PagingManager, addr: usize, page_count: usize) -> Option<Stack> {
+        if pm.mapper.translate_addr(VirtAddr::new(addr as u64)).is_some() {
+            return None;
+        }
+
+        let stack_base = addr + GUARD_SIZE as usize;
         for i in 0..page_count {
-            let page_addr = addr + i * 0x1000;
+            let page_addr = stack_base + i * 0x1000;
             let frame = pm.allocate_frame()?;
             pm.map_page(
                 Page::containing_address(VirtAddr::new(page_addr as u64)),
@@ -67,8 +180,8 @@ impl Stack {
         }
 
         Some(Stack {
-            stack_base: addr as u64,
-            stack_top: (addr + page_count * 0x1000) as u64,
+            stack_base: stack_base as u64,
+            stack_top: (stack_base + page_count * 0x1000) as u64,
         })
     }
 
@@ -132,19 +245,671 @@ pub enum Ring {
     Ring3
 }
 
+// syscall ABI used by a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Personality {
+    Illuminos,
+    Linux,
+}
+
+// flips the trace flag of a process, returns the new state
+pub fn toggle_trace(pid: usize) -> Option<bool> {
+    let mut table = PROCESS_TABLE.lock();
+    let process = table.get_mut(pid)?;
+    process.traced = !process.traced;
+    Some(process.traced)
+}
+
+pub fn current_personality() -> Personality {
+    PROCESS_TABLE
+        .lock()
+        .current()
+        .map(|process| process.personality)
+        .unwrap_or(Personality::Illuminos)
+}
+
+// user address space layout, the program is loaded where it was linked and
+// its heap starts right after it
+pub const MMAP_BASE: u64 = 0x1000_0000;
+pub const MMAP_END: u64 = 0x0000_7000_0000_0000;
+pub const USER_STACK_TOP: u64 = 0x0000_7fff_ffff_0000;
+pub const USER_STACK_SIZE: usize = 1024 * 1024;
+
 pub struct ProcessMemoryContext<'a> {
     pub paging_manager: PagingManager<'a>,
     page_table_addr: PhysFrame,
     entry_point: VirtAddr,
-    stack: Stack
+    stack: Stack,
+    heap_start: VirtAddr,
+    brk: VirtAddr,
+    user_rsp: u64,
+    vmas: VmaList,
+    // anonymous pages written to swap -> their slot
+    swapped: BTreeMap<Page, usize>,
+    // where the reclaim clock stopped
+    clock_hand: VirtAddr,
+    // a frame allocation failed even after reclaim
+    out_of_frames: bool,
+}
+
+impl<'a> ProcessMemoryContext<'a> {
+    pub fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    pub fn stack_top(&self) -> u64 {
+        self.stack.stack_top
+    }
+
+    pub fn vmas(&self) -> &VmaList {
+        &self.vmas
+    }
+
+    // rsp used when the process enters ring 3 for the first time
+    pub fn set_user_rsp(&mut self, rsp: u64) {
+        self.user_rsp = rsp;
+    }
+
+    // only before the heap has grown, the loader puts it after the program
+    pub fn set_heap_start(&mut self, addr: VirtAddr) {
+        let addr = addr.align_up(Size4KiB::SIZE);
+        self.heap_start = addr;
+        self.brk = addr;
+    }
+
+    // write into the address space of the process through the physical
+    // memory mapping, so it works whatever page table is active
+    pub fn write_bytes(&mut self, addr: VirtAddr, data: &[u8]) -> Option<()> {
+        let phys_offset = self.paging_manager.mapper.phys_offset();
+        let mut written = 0;
+
+        while written < data.len() {
+            let virt = addr + written as u64;
+            let page = Page::containing_address(virt);
+            if self.swapped.contains_key(&page) {
+                let flags = Self::vma_page_flags(self.vmas.find(virt)?);
+                self.swap_in(page, flags)?;
+            }
+
+            let phys = self.paging_manager.mapper.translate_addr(virt)?;
+            let len = (data.len() - written).min(4096 - (virt.as_u64() % 4096) as usize);
+            let dst = (phys_offset + phys.as_u64()).as_mut_ptr::<u8>();
+
+            unsafe {
+                dst.copy_from_nonoverlapping(data[written..].as_ptr(), len);
+            }
+            written += len;
+        }
+
+        Some(())
+    }
+
+    fn page_flags(prot: Protection) -> PageTableFlags {
+        // PROT_NONE pages stay present for the kernel, the user just can't
+        // reach them
+        let mut flags = PageTableFlags::PRESENT;
+
+        if !prot.is_empty() {
+            flags |= PageTableFlags::USER_ACCESSIBLE;
+        }
+        if prot.contains(Protection::WRITE) {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !prot.contains(Protection::EXEC) && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+
+        flags
+    }
+
+    // private file pages are shared with the page cache until written, they
+    // stay read only so the write can be caught
+    fn vma_page_flags(vma: &Vma) -> PageTableFlags {
+        let flags = Self::page_flags(vma.prot);
+
+        match vma.backing {
+            Backing::File { shared: false, .. } => flags - PageTableFlags::WRITABLE,
+            _ => flags,
+        }
+    }
+
+    fn frame_ptr(&self, frame: PhysFrame) -> *mut u8 {
+        (self.paging_manager.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr::<u8>()
+    }
+
+    fn mapped_page(&self, page: Page) -> Option<(PhysFrame, PageTableFlags)> {
+        match self.paging_manager.mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags, .. } => Some((frame, flags)),
+            _ => None,
+        }
+    }
+
+    // when memory is exhausted the process gives its own cold pages to swap
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.paging_manager.allocate_frame().or_else(|| {
+            self.reclaim(swap::RECLAIM_BATCH);
+            self.paging_manager.allocate_frame()
+        });
+
+        self.out_of_frames |= frame.is_none();
+        frame
+    }
+
+    // whether an allocation failed since the last call
+    pub fn take_out_of_frames(&mut self) -> bool {
+        core::mem::take(&mut self.out_of_frames)
+    }
+
+    fn map_zeroed(&mut self, page: Page, flags: PageTableFlags) -> Option<()> {
+        let frame = self.allocate_frame()?;
+
+        unsafe {
+            self.frame_ptr(frame).write_bytes(0, Size4KiB::SIZE as usize);
+        }
+
+        if self.map_frame(page, frame, flags).is_none() {
+            unsafe { self.paging_manager.frame_allocator.deallocate_frame(frame) };
+            return None;
+        }
+
+        Some(())
+    }
+
+    // private copy of a frame
+    fn map_copy(&mut self, page: Page, source: PhysFrame, flags: PageTableFlags) -> Option<()> {
+        let frame = self.allocate_frame()?;
+
+        unsafe {
+            self.frame_ptr(frame).copy_from_nonoverlapping(self.frame_ptr(source), Size4KiB::SIZE as usize);
+        }
+
+        if self.map_frame(page, frame, flags).is_none() {
+            unsafe { self.paging_manager.frame_allocator.deallocate_frame(frame) };
+            return None;
+        }
+
+        Some(())
+    }
+
+    fn map_frame(&mut self, page: Page, frame: PhysFrame, flags: PageTableFlags) -> Option<()> {
+        let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let result = unsafe {
+            self.paging_manager.mapper.map_to_with_table_flags(
+                page,
+                frame,
+                flags,
+                table_flags,
+                &mut self.paging_manager.frame_allocator,
+            )
+        };
+
+        result.ok()?.flush();
+        Some(())
+    }
+
+    fn unmap_pages(&mut self, start: VirtAddr, end: VirtAddr) {
+        if start >= end {
+            return;
+        }
+
+        let pages = Page::<Size4KiB>::range(Page::containing_address(start), Page::containing_address(end - 1u64) + 1);
+        for page in pages {
+            if let Ok((frame, flush)) = self.paging_manager.mapper.unmap(page) {
+                flush.flush();
+                unsafe { self.paging_manager.frame_allocator.deallocate_frame(frame) };
+            }
+        }
+
+        self.swapped.retain(|page, slot| {
+            let gone = pages.start <= *page && *page < pages.end;
+            if gone {
+                swap::free_slot(*slot);
+            }
+            !gone
+        });
+    }
+
+    fn map_pages(&mut self, start: VirtAddr, end: VirtAddr, prot: Protection) -> Option<()> {
+        let flags = Self::page_flags(prot);
+        let pages = Page::<Size4KiB>::range(Page::containing_address(start), Page::containing_address(end - 1u64) + 1);
+
+        for page in pages {
+            if self.map_zeroed(page, flags).is_none() {
+                self.unmap_pages(start, page.start_address());
+                return None;
+            }
+        }
+
+        Some(())
+    }
+
+    // new area of zeroed pages, `start` and `len` are page aligned
+    pub fn map_region(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        prot: Protection,
+        kind: VmaKind,
+        backing: Backing,
+    ) -> Option<VirtAddr> {
+        let end = start + len;
+        if len == 0 || !start.is_aligned(Size4KiB::SIZE) || end.as_u64() > USER_STACK_TOP {
+            return None;
+        }
+
+        self.vmas.insert(Vma::new(start, end, prot, kind, backing))?;
+        if self.map_pages(start, end, prot).is_none() {
+            self.vmas.remove_range(start, end);
+            return None;
+        }
+
+        Some(start)
+    }
+
+    pub fn unmap_region(&mut self, start: VirtAddr, len: u64) {
+        let end = start + len;
+
+        for vma in self.vmas.remove_range(start, end) {
+            match &vma.backing {
+                Backing::File { .. } => self.unmap_file_pages(&vma),
+                _ => self.unmap_pages(vma.start, vma.end),
+            }
+        }
+    }
+
+    // frames of the page cache go back to it, shared pages are written to
+    // the file first if they were modified
+    fn unmap_file_pages(&mut self, vma: &Vma) {
+        let Backing::File { file, offset, shared } = &vma.backing else {
+            return;
+        };
+
+        let file_size = fs::with_root(|fs| fs.metadata(fs::Path::new(&file.path)))
+            .map(|metadata| metadata.size)
+            .unwrap_or(0);
+        let mut cache = PAGE_CACHE.lock();
+
+        for page in Page::<Size4KiB>::range(Page::containing_address(vma.start), Page::containing_address(vma.end)) {
+            let Some((frame, flags)) = self.mapped_page(page) else {
+                continue;
+            };
+            let key = file.key(offset + (page.start_address() - vma.start));
+
+            if let Ok((_, flush)) = self.paging_manager.mapper.unmap(page) {
+                flush.flush();
+            }
+
+            if cache.contains_frame(frame) {
+                if *shared && flags.contains(PageTableFlags::DIRTY) {
+                    cache.write_back(key, &file.path, file_size);
+                }
+                cache.release(key);
+            } else {
+                unsafe { self.paging_manager.frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+
+    // file area whose pages are mapped when first touched, `backing` is a
+    // Backing::File
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        prot: Protection,
+        kind: VmaKind,
+        backing: Backing,
+    ) -> Option<VirtAddr> {
+        let end = start + len;
+        if len == 0 || !start.is_aligned(Size4KiB::SIZE) || end.as_u64() > USER_STACK_TOP {
+            return None;
+        }
+
+        self.vmas.insert(Vma::new(start, end, prot, kind, backing))?;
+        Some(start)
+    }
+
+    // frames of a shared memory object, each mapping owns one reference on
+    // them so unmap_pages gives them back like any other frame
+    pub fn map_shared(&mut self, start: VirtAddr, frames: &[PhysFrame], prot: Protection, id: usize) -> Option<VirtAddr> {
+        let len = frames.len() as u64 * Size4KiB::SIZE;
+        let end = start + len;
+        if len == 0 || !start.is_aligned(Size4KiB::SIZE) || end.as_u64() > USER_STACK_TOP {
+            return None;
+        }
+
+        self.vmas.insert(Vma::new(start, end, prot, VmaKind::Mmap, Backing::Shared { id }))?;
+
+        let flags = Self::page_flags(prot);
+        for (page, frame) in Page::<Size4KiB>::range(Page::containing_address(start), Page::containing_address(end)).zip(frames) {
+            self.paging_manager.frame_allocator.add_ref(*frame);
+            if self.map_frame(page, *frame, flags).is_none() {
+                unsafe { self.paging_manager.frame_allocator.deallocate_frame(*frame) };
+                self.unmap_region(start, len);
+                return None;
+            }
+        }
+
+        Some(start)
+    }
+
+    // msync, writes back the modified pages of the shared file areas
+    pub fn sync_region(&mut self, start: VirtAddr, len: u64) -> Option<()> {
+        let end = start + len;
+        let vmas: Vec<Vma> = self.vmas
+            .iter()
+            .filter(|vma| vma.start < end && start < vma.end)
+            .cloned()
+            .collect();
+
+        for vma in vmas {
+            let Backing::File { file, offset, shared: true } = &vma.backing else {
+                continue;
+            };
+
+            let file_size = fs::with_root(|fs| fs.metadata(fs::Path::new(&file.path)))
+                .map(|metadata| metadata.size)
+                .ok()?;
+            let cache = PAGE_CACHE.lock();
+            let first = Page::<Size4KiB>::containing_address(vma.start.max(start));
+            let last = Page::<Size4KiB>::containing_address(vma.end.min(end) - 1u64);
+
+            for page in Page::range_inclusive(first, last) {
+                let Some((_, flags)) = self.mapped_page(page) else {
+                    continue;
+                };
+                if !flags.contains(PageTableFlags::DIRTY) {
+                    continue;
+                }
+
+                cache.write_back(file.key(offset + (page.start_address() - vma.start)), &file.path, file_size)?;
+                if let Ok(flush) = unsafe { self.paging_manager.mapper.update_flags(page, flags - PageTableFlags::DIRTY) } {
+                    flush.flush();
+                }
+            }
+        }
+
+        Some(())
+    }
+
+    // demand paging of file areas, copy on write of their private pages and
+    // page in of swapped out pages
+    pub fn handle_fault(&mut self, addr: VirtAddr, write: bool) -> Option<()> {
+        let vma = self.vmas.find(addr)?.clone();
+        if vma.prot.is_empty() || (write && !vma.prot.contains(Protection::WRITE)) {
+            return None;
+        }
+
+        let page = Page::<Size4KiB>::containing_address(addr);
+        if self.swapped.contains_key(&page) {
+            return self.swap_in(page, Self::vma_page_flags(&vma));
+        }
+
+        let Backing::File { file, offset, shared } = &vma.backing else {
+            return None;
+        };
+        let key = file.key(offset + (page.start_address() - vma.start));
+        let mut cache = PAGE_CACHE.lock();
+
+        match self.mapped_page(page) {
+            // write to a private page still shared with the cache
+            Some((frame, _)) if write && !shared => {
+                let flags = Self::page_flags(vma.prot);
+
+                if cache.contains_frame(frame) {
+                    if let Ok((_, flush)) = self.paging_manager.mapper.unmap(page) {
+                        flush.flush();
+                    }
+                    let copied = self.map_copy(page, frame, flags);
+                    cache.release(key);
+                    copied
+                } else {
+                    let flush = unsafe { self.paging_manager.mapper.update_flags(page, flags) }.ok()?;
+                    flush.flush();
+                    Some(())
+                }
+            }
+            Some(_) => None,
+            None => {
+                let frame = cache.get(key, &file.path)?;
+
+                if write && !shared {
+                    let copied = self.map_copy(page, frame, Self::page_flags(vma.prot));
+                    cache.release(key);
+                    copied
+                } else if self.map_frame(page, frame, Self::vma_page_flags(&vma)).is_none() {
+                    cache.release(key);
+                    None
+                } else {
+                    Some(())
+                }
+            }
+        }
+    }
+
+    // clock over the anonymous pages: a page accessed since the hand last
+    // passed gets a second chance, the others are written to swap. returns
+    // the frames freed
+    pub fn reclaim(&mut self, count: usize) -> usize {
+        let ranges: Vec<(VirtAddr, VirtAddr)> = self.vmas
+            .iter()
+            .filter(|vma| vma.backing == Backing::Anonymous && vma.kind != VmaKind::Guard)
+            .map(|vma| (vma.start, vma.end))
+            .collect();
+        let pages = ranges
+            .iter()
+            .flat_map(|&(start, end)| Page::<Size4KiB>::range(Page::containing_address(start), Page::containing_address(end)));
+
+        let hand = self.clock_hand;
+        let total = pages.clone().count();
+        let behind = pages.clone().take_while(|page| page.start_address() < hand).count();
+
+        // two turns, the first one may only clear accessed bits
+        let mut freed = 0;
+        for page in pages.cycle().skip(behind).take(2 * total) {
+            if freed >= count {
+                break;
+            }
+            self.clock_hand = page.start_address() + Size4KiB::SIZE;
+
+            let Some((frame, flags)) = self.mapped_page(page) else {
+                continue;
+            };
+            if flags.contains(PageTableFlags::ACCESSED) {
+                if let Ok(flush) = unsafe { self.paging_manager.mapper.update_flags(page, flags - PageTableFlags::ACCESSED) } {
+                    flush.flush();
+                }
+            } else if self.swap_out(page, frame).is_some() {
+                freed += 1;
+            } else {
+                // swap is full
+                break;
+            }
+        }
+
+        freed
+    }
+
+    fn swap_out(&mut self, page: Page, frame: PhysFrame) -> Option<()> {
+        let data = unsafe { core::slice::from_raw_parts(self.frame_ptr(frame), Size4KiB::SIZE as usize) };
+        let slot = swap::write_page(data)?;
+
+        let Ok((_, flush)) = self.paging_manager.mapper.unmap(page) else {
+            swap::free_slot(slot);
+            return None;
+        };
+        flush.flush();
+        unsafe { self.paging_manager.frame_allocator.deallocate_frame(frame) };
+
+        self.swapped.insert(page, slot);
+        Some(())
+    }
+
+    fn swap_in(&mut self, page: Page, flags: PageTableFlags) -> Option<()> {
+        let slot = *self.swapped.get(&page)?;
+        let frame = self.allocate_frame()?;
+
+        let data = unsafe { core::slice::from_raw_parts_mut(self.frame_ptr(frame), Size4KiB::SIZE as usize) };
+        if swap::read_page(slot, data).is_none() || self.map_frame(page, frame, flags).is_none() {
+            unsafe { self.paging_manager.frame_allocator.deallocate_frame(frame) };
+            return None;
+        }
+
+        self.swapped.remove(&page);
+        swap::free_slot(slot);
+        Some(())
+    }
+
+    pub fn swapped_pages(&self) -> usize {
+        self.swapped.len()
+    }
+
+    // mapped pages by kind of area, guards are never mapped
+    pub fn footprint(&self) -> oom::Footprint {
+        let mut footprint = oom::Footprint { swapped: self.swapped.len(), ..Default::default() };
+
+        for vma in self.vmas.iter() {
+            let pages = Page::<Size4KiB>::range(Page::containing_address(vma.start), Page::containing_address(vma.end));
+            let resident = pages.filter(|page| self.mapped_page(*page).is_some()).count();
+
+            match vma.kind {
+                VmaKind::Program => footprint.program += resident,
+                VmaKind::Stack => footprint.stack += resident,
+                VmaKind::Heap => footprint.heap += resident,
+                VmaKind::Mmap => footprint.mmap += resident,
+                VmaKind::Guard => {}
+            }
+        }
+
+        footprint
+    }
+
+    pub fn protect_region(&mut self, start: VirtAddr, len: u64, prot: Protection) -> Option<()> {
+        let end = start + len;
+
+        // only the rights change, DIRTY still tells msync what to write back
+        // and ACCESSED what the reclaim clock saw
+        let rights = PageTableFlags::PRESENT
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE;
+
+        for vma in self.vmas.protect_range(start, end, prot)? {
+            let flags = Self::vma_page_flags(&vma) & rights;
+            let pages = Page::<Size4KiB>::range(Page::containing_address(vma.start), Page::containing_address(vma.end));
+            for page in pages {
+                let Some((_, current)) = self.mapped_page(page) else {
+                    continue;
+                };
+                if let Ok(flush) = unsafe { self.paging_manager.mapper.update_flags(page, (current - rights) | flags) } {
+                    flush.flush();
+                }
+            }
+        }
+
+        Some(())
+    }
+
+    // `hint` is used when it is free, like linux does without MAP_FIXED
+    pub fn find_free_region(&self, len: u64, hint: Option<VirtAddr>) -> Option<VirtAddr> {
+        if let Some(hint) = hint {
+            let end = hint.as_u64().checked_add(len)?;
+            if hint.as_u64() >= MMAP_BASE && end <= MMAP_END && self.vmas.is_free(hint, VirtAddr::new(end)) {
+                return Some(hint);
+            }
+        }
+
+        self.vmas.find_gap(len, VirtAddr::new(MMAP_BASE), VirtAddr::new(MMAP_END))
+    }
+
+    // the heap area covers [heap_start, brk) rounded to pages
+    pub fn set_brk(&mut self, addr: VirtAddr) -> Option<VirtAddr> {
+        if self.heap_start.is_null() || addr < self.heap_start || addr.as_u64() > MMAP_END {
+            return None;
+        }
+
+        let old_end = self.brk.align_up(Size4KiB::SIZE);
+        let new_end = addr.align_up(Size4KiB::SIZE);
+        let prot = Protection::READ | Protection::WRITE;
+
+        if new_end > old_end {
+            if !self.vmas.is_free(old_end, new_end) {
+                return None;
+            }
+            self.map_pages(old_end, new_end, prot)?;
+
+            match self.vmas.heap_mut() {
+                Some(heap) => heap.end = new_end,
+                None => {
+                    self.vmas.insert(Vma::new(old_end, new_end, prot, VmaKind::Heap, Backing::Anonymous))?;
+                }
+            }
+        } else if new_end < old_end {
+            self.unmap_region(new_end, old_end - new_end);
+        }
+
+        self.brk = addr;
+        Some(addr)
+    }
+
+    // gives back every frame of the process
+    pub fn release(&mut self) {
+        let vmas: Vec<(VirtAddr, u64)> = self.vmas.iter().map(|vma| (vma.start, vma.len())).collect();
+        for (start, len) in vmas {
+            self.unmap_region(start, len);
+        }
+        self.free_page_tables();
+    }
+
+    // the tables of the user half and the level 4 table, once nothing is
+    // mapped there anymore. the kernel's table is loaded first when this one
+    // is active, the kernel half is the same in both
+    fn free_page_tables(&mut self) {
+        let pml4_frame = self.page_table_addr;
+        if pml4_frame == kernel_page_table_frame() || pml4_frame.start_address().is_null() {
+            return;
+        }
+        if Cr3::read().0 == pml4_frame {
+            unsafe { Cr3::write(kernel_page_table_frame(), Cr3Flags::empty()) };
+        }
+
+        let phys_offset = self.paging_manager.mapper.phys_offset();
+        let frame_allocator = &mut self.paging_manager.frame_allocator;
+        for entry in self.paging_manager.mapper.level_4_table_mut().iter_mut().take(KERNEL_HALF_START) {
+            if let Ok(frame) = entry.frame() {
+                free_page_table(frame, 3, phys_offset, frame_allocator);
+            }
+            entry.set_unused();
+        }
+        unsafe { frame_allocator.deallocate_frame(pml4_frame) };
+    }
+}
+
+// a table of `level` and the tables below it, the pages they mapped are
+// already gone
+fn free_page_table(frame: PhysFrame, level: u8, phys_offset: VirtAddr, frame_allocator: &mut KernelFrameAllocator) {
+    if level > 1 {
+        let table = unsafe { &*(phys_offset + frame.start_address().as_u64()).as_ptr::<PageTable>() };
+        for entry in table.iter() {
+            if let Ok(next) = entry.frame() {
+                free_page_table(next, level - 1, phys_offset, frame_allocator);
+            }
+        }
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
 }
 
 
 
 pub struct Process<'a> {
+    pid: Pid,
     threads: Vec<Thread>,
     pub memory: ProcessMemoryContext<'a>,
+    pub files: FdTable,
     ring: Ring,
+    personality: Personality,
+    // syscalls are logged to the serial port
+    traced: bool,
 }
 
 impl<'a> Process<'a> {
@@ -156,9 +921,44 @@ impl<'a> Process<'a> {
             paging_manager,
             entry_point: VirtAddr::new(0x0),
             page_table_addr: PhysFrame::from_start_address(PhysAddr::new(0x0)).unwrap(),
-            stack
+            stack,
+            heap_start: HEAP_START,
+            brk: HEAP_START + HEAP_SIZE,
+            user_rsp: stack.stack_top,
+            vmas: VmaList::new(),
+            swapped: BTreeMap::new(),
+            clock_hand: VirtAddr::zero(),
+            out_of_frames: false,
         };
-        Process { threads, memory: process_memory_context, ring: Ring::Ring0 }
+        Process {
+            pid: Pid(0),
+            threads,
+            memory: process_memory_context,
+            files: FdTable::new(),
+            ring: Ring::Ring0,
+            personality: Personality::Illuminos,
+            traced: false,
+        }
+    }
+
+    pub fn pid(&self) -> usize {
+        self.pid.as_usize()
+    }
+
+    pub fn personality(&self) -> Personality {
+        self.personality
+    }
+
+    pub fn set_personality(&mut self, personality: Personality) {
+        self.personality = personality;
+    }
+
+    pub fn traced(&self) -> bool {
+        self.traced
+    }
+
+    pub fn set_traced(&mut self, traced: bool) {
+        self.traced = traced;
     }
 
     pub fn create_user_page_table(pm: &mut PagingManager) -> Option<(&'a mut PageTable, PhysFrame)> {
@@ -185,79 +985,90 @@ impl<'a> Process<'a> {
         println_serial!("{:?}", pm.mapper.phys_offset());
          */
 
+        // the new table is written through the physical memory mapping
         let frame = pm.allocate_frame()?;
-        let page = Page::containing_address(VirtAddr::new(0x3333_3333_3333));
-        pm.map_page(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
-
-        let start_addr = page.start_address().as_u64();
         let pml4 = unsafe {
-            let ptr = start_addr as *mut PageTable;
+            let ptr = (pm.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr::<PageTable>();
             ptr.write(PageTable::new());
             &mut *ptr
         };
 
-
-
-        for i in 0..512 {
-            pml4[i] = pm.mapper.level_4_table()[i].clone();
+        // only the kernel half is shared, the user half starts empty. the
+        // kernel entries are never reachable from ring 3
+        let kernel_table = pm.mapper.level_4_table();
+        for i in KERNEL_HALF_START..512 {
+            pml4[i] = kernel_table[i].clone();
+            let flags = pml4[i].flags();
+            if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                error!("kernel level 4 entry {} was user accessible", i);
+                pml4[i].set_flags(flags - PageTableFlags::USER_ACCESSIBLE);
+            }
         }
-        return Some((pml4, frame));
-        //return Some((pml4.clone(), frame));
-
 
+        Some((pml4, frame))
     }
 
+    // the program itself is mapped afterwards by the loader, see
+    // ProcessMemoryContext::map_region
     pub fn spawn_user(
         user_page_table: (&'a mut PageTable, PhysFrame),
         stack_size: usize,
-        mem_size: usize,
         entry_point: VirtAddr,
-        paging_manager: &'a mut PagingManager
+        paging_manager: &mut PagingManager<'a>
     ) -> Option<Process<'a>> {
         let user_offset_page_table = unsafe {
             OffsetPageTable::new(user_page_table.0, paging_manager.mapper.phys_offset())
         };
 
-        let mut user_pm = PagingManager {
+        let user_pm = PagingManager {
             mapper: user_offset_page_table,
             frame_allocator: paging_manager.frame_allocator.clone()
         };
 
+        let stack_size = (stack_size as u64).next_multiple_of(Size4KiB::SIZE);
+        let stack = Stack {
+            stack_base: USER_STACK_TOP - stack_size,
+            stack_top: USER_STACK_TOP,
+        };
 
+        let mut memory = ProcessMemoryContext {
+            paging_manager: user_pm,
+            page_table_addr: user_page_table.1,
+            entry_point,
+            stack,
+            heap_start: VirtAddr::zero(),
+            brk: VirtAddr::zero(),
+            user_rsp: stack.stack_top - 1,
+            vmas: VmaList::new(),
+            swapped: BTreeMap::new(),
+            clock_hand: VirtAddr::zero(),
+            out_of_frames: false,
+        };
 
-        let stack = Stack::allocate_with(&mut user_pm, 0x0030_0000, 2)?;
-
-        /*user_pm.map_page(
-            stack_page,
-            stack_frame,
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
-        );*/
-
-        // heap
-
-        reserve_memory(
-            VirtAddr::new(0x0060_0000),
-            mem_size as u64,
-            &mut user_pm.mapper,
-            &mut user_pm.frame_allocator,
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
-        );
-
-
-        // entry
-        reserve_memory(
-            VirtAddr::new(0x0050_0000),
-            0x0060_0000 - 0x0050_0000,
-            &mut user_pm.mapper,
-            &mut user_pm.frame_allocator,
-            PageTableFlags::PRESENT | PageTableFlags::BIT_9 | PageTableFlags::USER_ACCESSIBLE
-        );
-
+        memory.map_region(
+            VirtAddr::new(stack.stack_base),
+            stack_size,
+            Protection::READ | Protection::WRITE,
+            VmaKind::Stack,
+            Backing::Anonymous,
+        )?;
+        // reserved so nothing gets mapped right below the stack
+        memory.vmas.insert(Vma::new(
+            VirtAddr::new(stack.stack_base - GUARD_SIZE),
+            VirtAddr::new(stack.stack_base),
+            Protection::empty(),
+            VmaKind::Guard,
+            Backing::Anonymous,
+        ))?;
 
         return Some(Process {
+            pid: Pid::new(),
             threads: Vec::new(),
-            memory: ProcessMemoryContext { paging_manager: user_pm, page_table_addr: user_page_table.1, entry_point, stack },
-            ring: Ring::Ring3
+            memory,
+            files: FdTable::with_stdio(STDIO.fd()),
+            ring: Ring::Ring3,
+            personality: Personality::Illuminos,
+            traced: false,
         });
     }
 
@@ -267,25 +1078,120 @@ impl<'a> Process<'a> {
             return;
         }
 
-        Cr3::write(self.memory.page_table_addr, Cr3Flags::empty());
-        println_serial!("{:?}", self.memory.paging_manager.mapper.translate(VirtAddr::new(0x301ff7)));
+        CURRENT_PID.store(self.pid(), Ordering::SeqCst);
+        enter_user(self.memory.page_table_addr, self.memory.user_rsp, self.memory.entry_point);
+    }
+}
 
-        use x86_64::registers::segmentation::*;
-       // CS::set_reg(GDT.1.user_code_selector);
-        DS::set_reg(GDT.1.user_data_selector);
-        ES::set_reg(GDT.1.user_data_selector);
-        FS::set_reg(GDT.1.user_data_selector);
-        GS::set_reg(GDT.1.user_data_selector);
-        //CS::set_reg(GDT.1.user_code_selector);
+// run a process of the process table, the table is not locked while the
+// process runs so its syscalls can use it
+pub unsafe fn run(pid: usize) {
+    let (page_table_addr, user_rsp, entry_point) = {
+        let table = PROCESS_TABLE.lock();
+        let Some(process) = table.get(pid) else {
+            error!("No process with pid {}", pid);
+            return;
+        };
 
+        if process.ring == Ring::Ring0 {
+            error!("Can't execute a ring 0 process");
+            return;
+        }
 
+        (process.memory.page_table_addr, process.memory.user_rsp, process.memory.entry_point)
+    };
 
-        unsafe {
-            join_thread(self.memory.stack.stack_top - 1, self.memory.entry_point.as_u64());
+    CURRENT_PID.store(pid, Ordering::SeqCst);
+    enter_user(page_table_addr, user_rsp, entry_point);
+}
+
+unsafe fn enter_user(page_table_addr: PhysFrame, user_rsp: u64, entry_point: VirtAddr) {
+    Cr3::write(page_table_addr, Cr3Flags::empty());
+
+    use x86_64::registers::segmentation::*;
+    DS::set_reg(GDT.1.user_data_selector);
+    ES::set_reg(GDT.1.user_data_selector);
+    FS::set_reg(GDT.1.user_data_selector);
+    GS::set_reg(GDT.1.user_data_selector);
+
+    unsafe {
+        join_thread(user_rsp, entry_point.as_u64());
+    }
+    loop {}
+}
+
+// demand paging, false when the fault isn't for a lazily mapped page of the
+// current process. the table may already be locked by the faulting code
+pub fn handle_page_fault(addr: VirtAddr, write: bool) -> bool {
+    loop {
+        let Some(mut table) = PROCESS_TABLE.try_lock() else {
+            return false;
+        };
+
+        if swap::low_on_frames() {
+            table.reclaim(swap::RECLAIM_BATCH);
         }
-        loop {}
 
+        let Some(process) = table.current() else {
+            return false;
+        };
+        // only what fails during this fault counts
+        process.memory.take_out_of_frames();
+        if process.memory.handle_fault(addr, write).is_some() {
+            return true;
+        }
+        if !process.memory.take_out_of_frames() {
+            return false;
+        }
+
+        // try again once the largest process is gone, unless it is this one
+        if !oom::kill_largest(table) {
+            return false;
+        }
+    }
+}
+
+// thread whose stack guard contains `addr`, the main thread of a process has
+// the pid as id. user guards are areas of the current process
+pub fn stack_overflow_at(addr: VirtAddr) -> Option<usize> {
+    if addr.as_u64() < USER_STACK_TOP {
+        let mut table = PROCESS_TABLE.try_lock()?;
+        let process = table.current()?;
+        let vma = process.memory.vmas().find(addr)?;
+        return (vma.kind == VmaKind::Guard).then(|| process.pid());
+    }
+
+    let guard = addr.align_down(Size4KiB::SIZE).as_u64();
+    KERNEL_STACK_GUARDS.try_lock()?.get(&guard).copied()
+}
+
+// waits for the next interrupt. there is no scheduler yet, so a syscall that
+// blocks can only be woken by what an interrupt handler does
+pub fn wait_for_interrupt() {
+    let enabled = x86_64::instructions::interrupts::are_enabled();
+    x86_64::instructions::interrupts::enable_and_hlt();
+    if !enabled {
+        x86_64::instructions::interrupts::disable();
+    }
+}
+
+// exit status of a process killed by a fault, what a shell shows for SIGSEGV
+pub const EXIT_FAULT: i32 = 128 + 11;
+
+// terminate the current process, there is no scheduler yet so the cpu is
+// simply parked once the process is gone
+pub fn exit_current(status: i32) -> ! {
+    let pid = current_pid();
+    if let Some(mut process) = PROCESS_TABLE.lock().remove(pid) {
+        process.memory.release();
+        process.files.close_all();
+    }
+    CURRENT_PID.store(0, Ordering::SeqCst);
+
+    info!("process {} exited with status {}", pid, status);
 
+    loop {
+        x86_64::instructions::interrupts::enable_and_hlt();
     }
 }
 
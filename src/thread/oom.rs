@@ -0,0 +1,86 @@
+// out of memory killer, when frames or the kernel heap run out the process
+// using the most memory is terminated to get it back
+
+use spin::MutexGuard;
+
+use super::{current_pid, exit_current, ProcessTable, Ring, PROCESS_TABLE};
+use crate::{error, info};
+
+// exit status of a process killed for memory, what a shell shows for SIGKILL
+pub const EXIT_OOM: i32 = 128 + 9;
+
+// pages of a process, by kind of area
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Footprint {
+    pub program: usize,
+    pub stack: usize,
+    pub heap: usize,
+    pub mmap: usize,
+    pub swapped: usize,
+}
+
+impl Footprint {
+    pub fn resident(&self) -> usize {
+        self.program + self.stack + self.heap + self.mmap
+    }
+
+    // what killing the process gives back, frames and swap slots
+    pub fn total(&self) -> usize {
+        self.resident() + self.swapped
+    }
+}
+
+// logs every user process with its footprint and returns the largest one
+fn select_victim(table: &ProcessTable) -> Option<usize> {
+    info!("  pid  program  stack   heap   mmap  swapped");
+
+    let mut victim: Option<(usize, usize)> = None;
+    for (pid, process) in table.processes.iter().filter(|(_, process)| process.ring == Ring::Ring3) {
+        let footprint = process.memory.footprint();
+        info!(
+            "{:>5} {:>8} {:>6} {:>6} {:>6} {:>8}",
+            pid, footprint.program, footprint.stack, footprint.heap, footprint.mmap, footprint.swapped
+        );
+
+        if victim.is_none_or(|(_, total)| footprint.total() > total) {
+            victim = Some((*pid, footprint.total()));
+        }
+    }
+
+    victim.map(|(pid, _)| pid)
+}
+
+// kills the process with the largest footprint, false when there is none.
+// never returns when the victim is the current process
+pub fn kill_largest(mut table: MutexGuard<ProcessTable>) -> bool {
+    error!("out of memory, pages used by each process:");
+    let Some(pid) = select_victim(&table) else {
+        error!("out of memory and no process to kill");
+        return false;
+    };
+    error!("out of memory: killing process {}", pid);
+
+    if pid == current_pid() {
+        drop(table);
+        exit_current(EXIT_OOM);
+    }
+
+    let Some(mut victim) = table.remove(pid) else {
+        return false;
+    };
+    drop(table);
+
+    victim.memory.release();
+    victim.files.close_all();
+    info!("process {} exited with status {}", pid, EXIT_OOM);
+    true
+}
+
+// for code that doesn't hold the process table, false when it is busy or no
+// process could be killed
+pub fn out_of_memory() -> bool {
+    match PROCESS_TABLE.try_lock() {
+        Some(table) => kill_largest(table),
+        None => false,
+    }
+}
@@ -0,0 +1,92 @@
+// named shared memory objects, several processes can map the same frames
+//
+// the object owns one reference on each of its frames and every mapping one
+// more, so the frames outlive the object until the last mapper is gone
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+
+use crate::allocator::paging::{physical_memory_offset, KernelFrameAllocator};
+
+pub struct SharedObject {
+    pub name: String,
+    pub frames: Vec<PhysFrame>,
+}
+
+impl SharedObject {
+    pub fn len(&self) -> u64 {
+        self.frames.len() as u64 * Size4KiB::SIZE
+    }
+}
+
+pub struct ShmTable {
+    names: BTreeMap<String, usize>,
+    objects: BTreeMap<usize, SharedObject>,
+    next_id: usize,
+}
+
+pub static SHM: Mutex<ShmTable> = Mutex::new(ShmTable::new());
+
+impl ShmTable {
+    pub const fn new() -> Self {
+        ShmTable {
+            names: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    // zeroed object of `len` bytes rounded to pages, None if the name is taken
+    // or there is no memory left
+    pub fn create(&mut self, name: &str, len: u64) -> Option<usize> {
+        if len == 0 || self.names.contains_key(name) {
+            return None;
+        }
+
+        let mut allocator = KernelFrameAllocator::shared();
+        let mut frames = Vec::new();
+        for _ in 0..len.div_ceil(Size4KiB::SIZE) {
+            let Some(frame) = allocator.allocate_frame() else {
+                for frame in frames {
+                    unsafe { allocator.deallocate_frame(frame) };
+                }
+                return None;
+            };
+
+            let virt = physical_memory_offset() + frame.start_address().as_u64();
+            unsafe { virt.as_mut_ptr::<u8>().write_bytes(0, Size4KiB::SIZE as usize) };
+            frames.push(frame);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.names.insert(name.into(), id);
+        self.objects.insert(id, SharedObject { name: name.into(), frames });
+
+        Some(id)
+    }
+
+    pub fn open(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&SharedObject> {
+        self.objects.get(&id)
+    }
+
+    // the name is gone at once, the frames when the last mapping is
+    pub fn unlink(&mut self, name: &str) -> Option<()> {
+        let id = self.names.remove(name)?;
+        let object = self.objects.remove(&id)?;
+
+        let mut allocator = KernelFrameAllocator::shared();
+        for frame in object.frames {
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+
+        Some(())
+    }
+}
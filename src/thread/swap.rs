@@ -0,0 +1,142 @@
+// swap area on a disk, cold anonymous pages of the processes are written
+// there when physical memory runs low and read back when they fault
+//
+// a slot holds one page in SECTORS_PER_PAGE consecutive sectors. the page
+// tables don't know about slots, each process keeps which of its pages are
+// swapped out and where
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+use crate::allocator::paging::free_frames;
+use crate::drivers::disk::Disk;
+
+const SECTOR_SIZE: usize = 512;
+const SECTORS_PER_PAGE: u64 = Size4KiB::SIZE / SECTOR_SIZE as u64;
+
+// an MBR partition of this type holds a swap area
+const MBR_SWAP_TYPE: u8 = 0x82;
+const MBR_PARTITIONS: usize = 446;
+// the mkswap header takes the first page of the area, the magic ends it
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+const SWAP_LAST_PAGE: usize = 1028;
+
+// reclaim starts when fewer frames than this are left
+pub const LOW_WATERMARK: u64 = 256;
+// pages written out by one reclaim
+pub const RECLAIM_BATCH: usize = 32;
+
+pub struct SwapArea {
+    disk: Box<dyn Disk + Send>,
+    start_sector: u64,
+    used: Vec<bool>,
+    free: usize,
+}
+
+static SWAP: Mutex<Option<SwapArea>> = Mutex::new(None);
+
+// `sectors` sectors of `disk` from `start_sector` on, whatever was there is
+// lost
+pub fn enable(disk: Box<dyn Disk + Send>, start_sector: u64, sectors: u64) {
+    let pages = (sectors / SECTORS_PER_PAGE) as usize;
+    *SWAP.lock() = Some(SwapArea {
+        disk,
+        start_sector,
+        used: alloc::vec![false; pages],
+        free: pages,
+    });
+}
+
+// (first sector, sectors) of the swap area on a disk of `disk_sectors`
+// sectors, either an MBR partition of the swap type or a whole disk with a
+// mkswap header. the first page is left alone, it holds the header
+pub fn find_area(disk: &mut dyn Disk, disk_sectors: u64) -> Option<(u64, u64)> {
+    let mbr = disk.read_sector(0);
+    if mbr.get(510..512) == Some(&[0x55, 0xAA]) {
+        for entry in mbr.get(MBR_PARTITIONS..510)?.chunks_exact(16) {
+            let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+            let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+            if entry[4] == MBR_SWAP_TYPE && sectors > SECTORS_PER_PAGE {
+                return Some((start + SECTORS_PER_PAGE, sectors - SECTORS_PER_PAGE));
+            }
+        }
+    }
+
+    let magic_at = Size4KiB::SIZE as usize - SWAP_MAGIC.len();
+    let sector = disk.read_sector((magic_at / SECTOR_SIZE) as u64);
+    if sector.get(magic_at % SECTOR_SIZE..SECTOR_SIZE) != Some(SWAP_MAGIC) {
+        return None;
+    }
+
+    let sector = disk.read_sector((SWAP_LAST_PAGE / SECTOR_SIZE) as u64);
+    let at = SWAP_LAST_PAGE % SECTOR_SIZE;
+    let last_page = u32::from_le_bytes(sector.get(at..at + 4)?.try_into().unwrap()) as u64;
+    let sectors = ((last_page + 1) * SECTORS_PER_PAGE).min(disk_sectors);
+    (sectors > SECTORS_PER_PAGE).then(|| (SECTORS_PER_PAGE, sectors - SECTORS_PER_PAGE))
+}
+
+pub fn enabled() -> bool {
+    SWAP.lock().is_some()
+}
+
+// (slots used, slots), None without swap
+pub fn usage() -> Option<(usize, usize)> {
+    let swap = SWAP.lock();
+    let swap = swap.as_ref()?;
+    Some((swap.used.len() - swap.free, swap.used.len()))
+}
+
+// true when reclaim should run, never without a swap area to write to
+pub fn low_on_frames() -> bool {
+    free_frames() < LOW_WATERMARK && SWAP.lock().as_ref().is_some_and(|swap| swap.free > 0)
+}
+
+impl SwapArea {
+    fn sectors(&self, slot: usize) -> core::ops::Range<u64> {
+        let first = self.start_sector + slot as u64 * SECTORS_PER_PAGE;
+        first..first + SECTORS_PER_PAGE
+    }
+}
+
+// copies the page to a free slot, None when the swap area is full
+pub fn write_page(page: &[u8]) -> Option<usize> {
+    let mut swap = SWAP.lock();
+    let swap = swap.as_mut()?;
+
+    let slot = swap.used.iter().position(|used| !used)?;
+    swap.used[slot] = true;
+    swap.free -= 1;
+
+    for (sector, data) in swap.sectors(slot).zip(page.chunks(SECTOR_SIZE)) {
+        swap.disk.write_sector(sector, data);
+    }
+
+    Some(slot)
+}
+
+// the slot stays used, see free_slot
+pub fn read_page(slot: usize, page: &mut [u8]) -> Option<()> {
+    let mut swap = SWAP.lock();
+    let swap = swap.as_mut()?;
+    if !*swap.used.get(slot)? {
+        return None;
+    }
+
+    for (sector, data) in swap.sectors(slot).zip(page.chunks_mut(SECTOR_SIZE)) {
+        let sector = swap.disk.read_sector(sector);
+        data.copy_from_slice(sector.get(..data.len())?);
+    }
+
+    Some(())
+}
+
+pub fn free_slot(slot: usize) {
+    if let Some(swap) = SWAP.lock().as_mut()
+        && let Some(used) = swap.used.get_mut(slot).filter(|used| **used)
+    {
+        *used = false;
+        swap.free += 1;
+    }
+}
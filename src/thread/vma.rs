@@ -0,0 +1,182 @@
+// virtual memory areas of a process, every user mapping belongs to one
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use x86_64::VirtAddr;
+
+use crate::fs::page_cache::FileRef;
+
+bitflags! {
+    // same values as the PROT_* of mmap
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Protection: u64 {
+        const READ = 0x1;
+        const WRITE = 0x2;
+        const EXEC = 0x4;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    Program,
+    Stack,
+    Heap,
+    Mmap,
+    // never mapped, catches the overflows of the stack above it
+    Guard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backing {
+    Anonymous,
+    // pages come from the page cache when first touched, `offset` is the
+    // file offset of the start of the area
+    File { file: FileRef, offset: u64, shared: bool },
+    // frames of a shared memory object, mapped for the whole area
+    Shared { id: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub prot: Protection,
+    pub kind: VmaKind,
+    pub backing: Backing,
+}
+
+impl Vma {
+    pub fn new(start: VirtAddr, end: VirtAddr, prot: Protection, kind: VmaKind, backing: Backing) -> Self {
+        Vma { start, end, prot, kind, backing }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    // the part starting at `addr` is returned, `self` keeps the part before
+    fn split_off(&mut self, addr: VirtAddr) -> Vma {
+        let mut upper = self.clone();
+        upper.start = addr;
+        if let Backing::File { offset, .. } = &mut upper.backing {
+            *offset += addr - self.start;
+        }
+
+        self.end = addr;
+        upper
+    }
+}
+
+// sorted by start address, areas never overlap
+#[derive(Debug, Default)]
+pub struct VmaList {
+    vmas: BTreeMap<u64, Vma>,
+}
+
+impl VmaList {
+    pub const fn new() -> Self {
+        VmaList { vmas: BTreeMap::new() }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.values()
+    }
+
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.vmas
+            .range(..=addr.as_u64())
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    pub fn find_mut(&mut self, addr: VirtAddr) -> Option<&mut Vma> {
+        self.vmas
+            .range_mut(..=addr.as_u64())
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    pub fn is_free(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        !self.vmas.values().any(|vma| vma.start < end && start < vma.end)
+    }
+
+    // a guard page the range would remove
+    pub fn has_guard(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.vmas.values().any(|vma| vma.kind == VmaKind::Guard && vma.start < end && start < vma.end)
+    }
+
+    pub fn insert(&mut self, vma: Vma) -> Option<()> {
+        if vma.start >= vma.end || !self.is_free(vma.start, vma.end) {
+            return None;
+        }
+
+        self.vmas.insert(vma.start.as_u64(), vma);
+        Some(())
+    }
+
+    // lowest gap of `len` bytes between `from` and `limit`
+    pub fn find_gap(&self, len: u64, from: VirtAddr, limit: VirtAddr) -> Option<VirtAddr> {
+        let mut candidate = from;
+
+        for vma in self.vmas.values() {
+            if vma.end <= candidate {
+                continue;
+            }
+            if vma.start >= candidate + len {
+                break;
+            }
+            candidate = vma.end;
+        }
+
+        (candidate + len <= limit).then_some(candidate)
+    }
+
+    // cut the areas crossing `start` or `end`, so that [start, end) is only
+    // made of whole areas
+    fn split_range(&mut self, start: VirtAddr, end: VirtAddr) {
+        for addr in [start, end] {
+            if let Some(vma) = self.find_mut(addr)
+                && vma.start != addr
+            {
+                let upper = vma.split_off(addr);
+                self.vmas.insert(upper.start.as_u64(), upper);
+            }
+        }
+    }
+
+    // removes [start, end) and returns the removed parts
+    pub fn remove_range(&mut self, start: VirtAddr, end: VirtAddr) -> Vec<Vma> {
+        self.split_range(start, end);
+
+        let keys: Vec<u64> = self.vmas.range(start.as_u64()..end.as_u64()).map(|(key, _)| *key).collect();
+        keys.into_iter().filter_map(|key| self.vmas.remove(&key)).collect()
+    }
+
+    // changes the protection of [start, end), which has to be fully mapped
+    pub fn protect_range(&mut self, start: VirtAddr, end: VirtAddr, prot: Protection) -> Option<Vec<Vma>> {
+        let mut addr = start;
+        while addr < end {
+            addr = self.find(addr)?.end;
+        }
+
+        self.split_range(start, end);
+
+        let mut changed = Vec::new();
+        for (_, vma) in self.vmas.range_mut(start.as_u64()..end.as_u64()) {
+            vma.prot = prot;
+            changed.push(vma.clone());
+        }
+        Some(changed)
+    }
+
+    pub fn heap_mut(&mut self) -> Option<&mut Vma> {
+        self.vmas.values_mut().find(|vma| vma.kind == VmaKind::Heap)
+    }
+}
@@ -0,0 +1,47 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::drivers::rtc::{self, DateTime};
+use crate::io::outb;
+
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_BASE_FREQUENCY: u64 = 1_193_182;
+
+// channel 0, lobyte/hibyte, rate generator
+const PIT_MODE_RATE_GENERATOR: u8 = 0x36;
+
+pub const TIMER_FREQUENCY: u64 = 100; // Hz
+
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init_clock() {
+    let divisor = (PIT_BASE_FREQUENCY / TIMER_FREQUENCY) as u16;
+    outb(PIT_COMMAND, PIT_MODE_RATE_GENERATOR);
+    outb(PIT_CHANNEL0, (divisor & 0xFF) as u8);
+    outb(PIT_CHANNEL0, (divisor >> 8) as u8);
+
+    BOOT_TIME.store(rtc::read_rtc().to_unix_timestamp(), Ordering::SeqCst);
+}
+
+// called from the timer interrupt
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TIMER_FREQUENCY
+}
+
+// POSIX time of the wall clock
+pub fn now() -> u64 {
+    BOOT_TIME.load(Ordering::SeqCst) + ticks() / TIMER_FREQUENCY
+}
+
+pub fn now_datetime() -> DateTime {
+    DateTime::from_unix_timestamp(now())
+}
@@ -0,0 +1,17 @@
+# build the user programs and copy them into the ext2 test image
+# usage: user/install.sh [disk image], disk.img by default
+set -xe
+
+cd "$(dirname "$0")/.."
+DISK=${1:-disk.img}
+BIN_DIR=target/x86_64-unknown-none/release
+
+# code for a fixed address, build.rs links it with --no-pie
+RUSTFLAGS="-C relocation-model=static" \
+    cargo build --release -p illuminos-programs --target x86_64-unknown-none
+
+for program in user/programs/src/bin/*.rs; do
+    name=$(basename "$program" .rs)
+    debugfs -w -R "rm /$name" "$DISK" || true
+    debugfs -w -R "write $BIN_DIR/$name /$name" "$DISK"
+done
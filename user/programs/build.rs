@@ -0,0 +1,10 @@
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let script = format!("{}/../sdk/link.ld", manifest_dir);
+
+    println!("cargo:rerun-if-changed={}", script);
+    println!("cargo:rustc-link-arg-bins=-T{}", script);
+    println!("cargo:rustc-link-arg-bins=-static");
+    // the target defaults to static-pie, the kernel only loads ET_EXEC
+    println!("cargo:rustc-link-arg-bins=--no-pie");
+}
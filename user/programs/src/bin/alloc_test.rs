@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+use illuminos_sdk::alloc::{string::String, vec::Vec};
+use illuminos_sdk::println;
+
+#[unsafe(no_mangle)]
+extern "C" fn main() -> i32 {
+    // enough to grow the heap with brk several times
+    let mut numbers = Vec::new();
+    for i in 0..100_000u64 {
+        numbers.push(i * i);
+    }
+    let sum: u64 = numbers.iter().sum();
+
+    let mut text = String::new();
+    for word in ["heap", "works"] {
+        text.push_str(word);
+        text.push(' ');
+    }
+
+    println!("{}: {} squares, sum {}", text.trim_end(), numbers.len(), sum);
+    0
+}
@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use illuminos_sdk::println;
+
+#[unsafe(no_mangle)]
+extern "C" fn main() -> i32 {
+    println!("Hello from user space, pid {}", illuminos_sdk::process::id());
+    0
+}
@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+use illuminos_sdk::{fs, println, syscall};
+
+#[unsafe(no_mangle)]
+extern "C" fn main() -> i32 {
+    let entries = match fs::read_dir("/") {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("ls: /: error {}", e.0);
+            return 1;
+        }
+    };
+
+    for entry in entries {
+        let suffix = if entry.kind as u32 == syscall::STAT_KIND_DIR { "/" } else { "" };
+        println!("{:>8} {}{}", entry.inode, entry.name, suffix);
+    }
+    0
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+use illuminos_sdk::println;
+use illuminos_sdk::syscall::{self, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+#[unsafe(no_mangle)]
+extern "C" fn main() -> i32 {
+    let len = 4 * 4096;
+    let addr = match syscall::mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, 0, 0) {
+        Ok(addr) => addr,
+        Err(e) => {
+            println!("mmap failed: {}", e.0);
+            return 1;
+        }
+    };
+
+    let pages = unsafe { core::slice::from_raw_parts_mut(addr, len as usize) };
+    pages.fill(0xAB);
+    println!("mapped {} bytes at {:p}, last byte {:#x}", len, addr, pages[len as usize - 1]);
+
+    // read only, a write would now fault
+    let _ = syscall::mprotect(addr, len, PROT_READ);
+    let _ = syscall::munmap(addr, len);
+    println!("unmapped");
+    0
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+use illuminos_sdk::println;
+use illuminos_sdk::syscall;
+
+#[unsafe(no_mangle)]
+extern "C" fn main() -> i32 {
+    let (reader, writer) = match syscall::pipe() {
+        Ok(fds) => fds,
+        Err(e) => {
+            println!("pipe failed: {}", e.0);
+            return 1;
+        }
+    };
+
+    let message = b"through the pipe";
+    if syscall::write(writer, message) != Ok(message.len()) {
+        println!("write failed");
+        return 1;
+    }
+    let _ = syscall::close(writer);
+
+    // the buffered bytes come first, then the end of file
+    let mut buf = [0u8; 64];
+    let len = syscall::read(reader, &mut buf).unwrap_or(0);
+    let eof = syscall::read(reader, &mut buf[len..]);
+    println!(
+        "read {:?}, then {:?}",
+        core::str::from_utf8(&buf[..len]).unwrap_or("?"),
+        eof.map_err(|e| e.0)
+    );
+
+    let _ = syscall::close(reader);
+    0
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+use illuminos_sdk::println;
+use illuminos_sdk::syscall::{self, PROT_READ, PROT_WRITE};
+
+#[unsafe(no_mangle)]
+extern "C" fn main() -> i32 {
+    let len = 2 * 4096;
+    let id = match syscall::shm_create("shm_test", len) {
+        Ok(id) => id,
+        Err(e) => {
+            println!("shm_create failed: {}", e.0);
+            return 1;
+        }
+    };
+
+    // two mappings of the same object see the same bytes
+    let (Ok(first), Ok(second)) = (
+        syscall::shm_map(id, 0, PROT_READ | PROT_WRITE),
+        syscall::shm_open("shm_test").and_then(|id| syscall::shm_map(id, 0, PROT_READ)),
+    ) else {
+        println!("shm_map failed");
+        return 1;
+    };
+
+    unsafe { first.add(len as usize - 1).write(0x42) };
+    println!("mapped at {:p} and {:p}, last byte {:#x}", first, second, unsafe {
+        second.add(len as usize - 1).read()
+    });
+
+    let _ = syscall::shm_unlink("shm_test");
+    let _ = syscall::munmap(first, len);
+    let _ = syscall::munmap(second, len);
+    0
+}
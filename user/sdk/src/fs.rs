@@ -0,0 +1,88 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::syscall::{self, Dirent, Errno, Result, Stat};
+
+pub struct File {
+    fd: usize,
+}
+
+impl File {
+    pub fn open(path: &str, flags: u64) -> Result<File> {
+        syscall::open(path, flags).map(|fd| File { fd })
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        syscall::read(self.fd, buf)
+    }
+
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut buf = [0; 512];
+        loop {
+            let n = self.read(&mut buf)?;
+            if n == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        syscall::write(self.fd, buf)
+    }
+
+    pub fn seek(&mut self, offset: i64, whence: u64) -> Result<u64> {
+        syscall::lseek(self.fd, offset, whence)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.fd);
+    }
+}
+
+pub fn metadata(path: &str) -> Result<Stat> {
+    syscall::stat(path)
+}
+
+pub struct DirEntry {
+    pub inode: u64,
+    pub kind: u8,
+    pub name: String,
+}
+
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>> {
+    let file = File::open(path, syscall::O_READ)?;
+    let header_size = core::mem::size_of::<Dirent>();
+    let mut entries = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    loop {
+        let len = syscall::getdents(file.fd(), &mut buf)?;
+        if len == 0 {
+            return Ok(entries);
+        }
+
+        let mut offset = 0;
+        while offset < len {
+            let header = unsafe { (buf.as_ptr().add(offset) as *const Dirent).read_unaligned() };
+            if header.record_len == 0 {
+                return Err(Errno(syscall::EINVAL));
+            }
+
+            let name = &buf[offset + header_size..offset + header_size + header.name_len as usize];
+            entries.push(DirEntry {
+                inode: header.inode,
+                kind: header.kind,
+                name: String::from_utf8_lossy(name).into_owned(),
+            });
+            offset += header.record_len as usize;
+        }
+    }
+}
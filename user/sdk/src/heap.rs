@@ -0,0 +1,66 @@
+// global allocator, the heap starts at the initial break and is grown with
+// brk when it runs out
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use crate::syscall;
+
+const GROW_SIZE: usize = 64 * 1024;
+
+struct BrkHeap {
+    heap: Mutex<Option<Heap>>,
+}
+
+#[global_allocator]
+static ALLOCATOR: BrkHeap = BrkHeap { heap: Mutex::new(None) };
+
+impl BrkHeap {
+    // moves the break by at least `size` bytes, returns the added size
+    fn grow(heap: &mut Option<Heap>, size: usize) -> Option<usize> {
+        let size = (size + GROW_SIZE - 1) & !(GROW_SIZE - 1);
+        let start = syscall::brk(0).ok()?;
+        let end = syscall::brk(start + size as u64).ok()?;
+        if end < start + size as u64 {
+            return None;
+        }
+
+        match heap {
+            Some(heap) => unsafe { heap.extend(size) },
+            None => {
+                let mut new = Heap::empty();
+                unsafe { new.init(start as usize, size) };
+                *heap = Some(new);
+            }
+        }
+
+        Some(size)
+    }
+}
+
+unsafe impl GlobalAlloc for BrkHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+
+        if let Some(ptr) = heap.as_mut().and_then(|heap| heap.allocate_first_fit(layout).ok()) {
+            return ptr.as_ptr();
+        }
+
+        // room for the alignment padding and the allocator's own hole header
+        if Self::grow(&mut heap, layout.size() + layout.align() + 64).is_none() {
+            return ptr::null_mut();
+        }
+
+        heap.as_mut()
+            .and_then(|heap| heap.allocate_first_fit(layout).ok())
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(heap) = self.heap.lock().as_mut() {
+            unsafe { heap.deallocate(NonNull::new_unchecked(ptr), layout) };
+        }
+    }
+}
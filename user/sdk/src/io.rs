@@ -0,0 +1,50 @@
+use core::fmt::{self, Write};
+
+use crate::syscall;
+
+// every process starts with these three open on the console
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+pub struct FdWriter(pub usize);
+
+impl Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match syscall::write(self.0, buf) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(n) => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: usize, args: fmt::Arguments) {
+    let _ = FdWriter(fd).write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,21 @@
+// runtime for illuminos user programs, using the native syscall ABI
+//
+// a program is `#![no_std]` and `#![no_main]` and defines
+// `#[unsafe(no_mangle)] extern "C" fn main() -> i32`
+
+#![no_std]
+
+pub extern crate alloc;
+
+pub mod syscall;
+pub mod io;
+pub mod fs;
+pub mod process;
+mod heap;
+mod start;
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    eprintln!("panic: {}", info);
+    process::exit(101)
+}
@@ -0,0 +1,9 @@
+use crate::syscall;
+
+pub fn exit(status: i32) -> ! {
+    syscall::exit(status)
+}
+
+pub fn id() -> usize {
+    syscall::getpid()
+}
@@ -0,0 +1,23 @@
+use core::arch::global_asm;
+
+use crate::process;
+
+unsafe extern "C" {
+    fn main() -> i32;
+}
+
+// the kernel doesn't align the initial stack
+global_asm!(
+    ".section .text._start",
+    ".globl _start",
+    "_start:",
+    "    and rsp, -16",
+    "    call {start}",
+    "    ud2",
+    start = sym start,
+);
+
+extern "C" fn start() -> ! {
+    let status = unsafe { main() };
+    process::exit(status)
+}
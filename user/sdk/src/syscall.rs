@@ -0,0 +1,271 @@
+// raw native syscalls, same numbers as the kernel's src/syscall/mod.rs
+// syscall number in rax, arguments in rdi, rsi, rdx, r10, r8, r9
+
+use core::arch::asm;
+
+pub const SYS_READ: u64 = 1;
+pub const SYS_WRITE: u64 = 2;
+pub const SYS_OPEN: u64 = 3;
+pub const SYS_CLOSE: u64 = 4;
+pub const SYS_LSEEK: u64 = 5;
+pub const SYS_STAT: u64 = 6;
+pub const SYS_GETDENTS: u64 = 7;
+pub const SYS_GETPID: u64 = 8;
+pub const SYS_EXIT: u64 = 9;
+pub const SYS_BRK: u64 = 10;
+pub const SYS_MMAP: u64 = 11;
+pub const SYS_MUNMAP: u64 = 12;
+pub const SYS_MPROTECT: u64 = 13;
+pub const SYS_MSYNC: u64 = 14;
+pub const SYS_SHM_CREATE: u64 = 15;
+pub const SYS_SHM_OPEN: u64 = 16;
+pub const SYS_SHM_MAP: u64 = 17;
+pub const SYS_SHM_UNLINK: u64 = 18;
+pub const SYS_DUP: u64 = 19;
+pub const SYS_DUP2: u64 = 20;
+pub const SYS_PIPE: u64 = 21;
+pub const SYS_POLL: u64 = 22;
+pub const SYS_IOCTL: u64 = 23;
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+pub const O_READ: u64 = 1;
+pub const O_WRITE: u64 = 1 << 1;
+pub const O_APPEND: u64 = 1 << 2;
+pub const O_CREATE: u64 = 1 << 4;
+pub const O_TRUNCATE: u64 = 1 << 5;
+pub const O_EXCL: u64 = 1 << 6;
+
+pub const PROT_NONE: u64 = 0x0;
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+pub const POLLIN: i16 = 0x01;
+pub const POLLOUT: i16 = 0x04;
+pub const POLLERR: i16 = 0x08;
+pub const POLLHUP: i16 = 0x10;
+pub const POLLNVAL: i16 = 0x20;
+
+// ioctl requests of the serial ports, /dev/ttyS0 to /dev/ttyS3
+pub const UART_SET_BAUD: u64 = 1;
+pub const UART_GET_BAUD: u64 = 2;
+
+pub const STAT_KIND_OTHER: u32 = 0;
+pub const STAT_KIND_FILE: u32 = 1;
+pub const STAT_KIND_DIR: u32 = 2;
+
+pub const ENOENT: i64 = 2;
+pub const EBADF: i64 = 9;
+pub const ENOMEM: i64 = 12;
+pub const EACCES: i64 = 13;
+pub const EFAULT: i64 = 14;
+pub const EEXIST: i64 = 17;
+pub const EINVAL: i64 = 22;
+pub const EMFILE: i64 = 24;
+pub const EPIPE: i64 = 32;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stat {
+    pub inode: u64,
+    pub size: u64,
+    pub kind: u32,
+    pub perm: u32,
+    pub access_time: u64,
+    pub modif_time: u64,
+    pub creation_time: u64,
+}
+
+// followed by the nul terminated name, records are 8 bytes aligned
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dirent {
+    pub inode: u64,
+    pub record_len: u16,
+    pub kind: u8,
+    pub name_len: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+// a negative value returned by the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i64);
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+pub unsafe fn syscall3(id: u64, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    let ret: i64;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") id as i64 => ret,
+            in("rdi") arg0,
+            in("rsi") arg1,
+            in("rdx") arg2,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    ret
+}
+
+pub unsafe fn syscall6(id: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> i64 {
+    let ret: i64;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") id as i64 => ret,
+            in("rdi") arg0,
+            in("rsi") arg1,
+            in("rdx") arg2,
+            in("r10") arg3,
+            in("r8") arg4,
+            in("r9") arg5,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    ret
+}
+
+fn check(ret: i64) -> Result<u64> {
+    if ret < 0 {
+        Err(Errno(-ret))
+    } else {
+        Ok(ret as u64)
+    }
+}
+
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_READ, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) })
+        .map(|n| n as usize)
+}
+
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_WRITE, fd as u64, buf.as_ptr() as u64, buf.len() as u64) })
+        .map(|n| n as usize)
+}
+
+pub fn open(path: &str, flags: u64) -> Result<usize> {
+    check(unsafe { syscall3(SYS_OPEN, path.as_ptr() as u64, path.len() as u64, flags) })
+        .map(|fd| fd as usize)
+}
+
+pub fn close(fd: usize) -> Result<()> {
+    check(unsafe { syscall3(SYS_CLOSE, fd as u64, 0, 0) }).map(|_| ())
+}
+
+// new descriptor on the same open file, the lowest free one
+pub fn dup(fd: usize) -> Result<usize> {
+    check(unsafe { syscall3(SYS_DUP, fd as u64, 0, 0) }).map(|fd| fd as usize)
+}
+
+// `new` is closed first if it was open
+pub fn dup2(old: usize, new: usize) -> Result<usize> {
+    check(unsafe { syscall3(SYS_DUP2, old as u64, new as u64, 0) }).map(|fd| fd as usize)
+}
+
+// (read end, write end)
+pub fn pipe() -> Result<(usize, usize)> {
+    let mut fds = [0i32; 2];
+    check(unsafe { syscall3(SYS_PIPE, fds.as_mut_ptr() as u64, 0, 0) })?;
+    Ok((fds[0] as usize, fds[1] as usize))
+}
+
+// waits for one of `fds` to be ready, at most `timeout` ms when it isn't
+// negative. returns how many are
+pub fn poll(fds: &mut [PollFd], timeout: i32) -> Result<usize> {
+    check(unsafe { syscall3(SYS_POLL, fds.as_mut_ptr() as u64, fds.len() as u64, timeout as u64) })
+        .map(|n| n as usize)
+}
+
+// a device request, what `request` and `arg` mean is up to the device
+pub fn ioctl(fd: usize, request: u64, arg: u64) -> Result<u64> {
+    check(unsafe { syscall3(SYS_IOCTL, fd as u64, request, arg) })
+}
+
+pub fn lseek(fd: usize, offset: i64, whence: u64) -> Result<u64> {
+    check(unsafe { syscall3(SYS_LSEEK, fd as u64, offset as u64, whence) })
+}
+
+pub fn stat(path: &str) -> Result<Stat> {
+    let mut stat = Stat::default();
+    check(unsafe {
+        syscall3(SYS_STAT, path.as_ptr() as u64, path.len() as u64, &mut stat as *mut Stat as u64)
+    })?;
+    Ok(stat)
+}
+
+pub fn getdents(fd: usize, buf: &mut [u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_GETDENTS, fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64) })
+        .map(|n| n as usize)
+}
+
+pub fn getpid() -> usize {
+    unsafe { syscall3(SYS_GETPID, 0, 0, 0) as usize }
+}
+
+pub fn exit(status: i32) -> ! {
+    unsafe { syscall3(SYS_EXIT, status as u64, 0, 0) };
+    unreachable!()
+}
+
+// with 0 returns the current break
+pub fn brk(addr: u64) -> Result<u64> {
+    check(unsafe { syscall3(SYS_BRK, addr, 0, 0) })
+}
+
+pub fn mmap(addr: u64, len: u64, prot: u64, flags: u64, fd: usize, offset: u64) -> Result<*mut u8> {
+    check(unsafe { syscall6(SYS_MMAP, addr, len, prot, flags, fd as u64, offset) })
+        .map(|addr| addr as *mut u8)
+}
+
+pub fn munmap(addr: *mut u8, len: u64) -> Result<()> {
+    check(unsafe { syscall3(SYS_MUNMAP, addr as u64, len, 0) }).map(|_| ())
+}
+
+pub fn mprotect(addr: *mut u8, len: u64, prot: u64) -> Result<()> {
+    check(unsafe { syscall3(SYS_MPROTECT, addr as u64, len, prot) }).map(|_| ())
+}
+
+// writes the dirty pages of a shared file mapping back to the file
+pub fn msync(addr: *mut u8, len: u64) -> Result<()> {
+    check(unsafe { syscall3(SYS_MSYNC, addr as u64, len, 0) }).map(|_| ())
+}
+
+// shared memory object of `size` bytes, zeroed, returns its id
+pub fn shm_create(name: &str, size: u64) -> Result<usize> {
+    check(unsafe { syscall3(SYS_SHM_CREATE, name.as_ptr() as u64, name.len() as u64, size) })
+        .map(|id| id as usize)
+}
+
+pub fn shm_open(name: &str) -> Result<usize> {
+    check(unsafe { syscall3(SYS_SHM_OPEN, name.as_ptr() as u64, name.len() as u64, 0) })
+        .map(|id| id as usize)
+}
+
+// maps the whole object, `addr` is only a hint
+pub fn shm_map(id: usize, addr: u64, prot: u64) -> Result<*mut u8> {
+    check(unsafe { syscall3(SYS_SHM_MAP, id as u64, addr, prot) }).map(|addr| addr as *mut u8)
+}
+
+pub fn shm_unlink(name: &str) -> Result<()> {
+    check(unsafe { syscall3(SYS_SHM_UNLINK, name.as_ptr() as u64, name.len() as u64, 0) }).map(|_| ())
+}